@@ -0,0 +1,54 @@
+use anyhow::{anyhow, Result};
+
+pub const USAGE: &str = "Usage: discord-rpc-helper [OPTIONS]
+
+Options:
+  --prefetch  Look up the metadata of all installed games once, then exit
+  -h, --help  Print this help";
+
+/// What to do, from the command line arguments
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    /// Show the running games as Discord activity
+    Run,
+    /// Warm the metadata cache for the installed games and exit
+    Prefetch,
+    Help,
+}
+
+/// Parses the command line arguments, without the program name
+pub fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Command> {
+    let mut command = Command::Run;
+
+    for arg in args {
+        command = match arg.as_str() {
+            "--prefetch" => Command::Prefetch,
+            "-h" | "--help" => return Ok(Command::Help),
+            other => return Err(anyhow!("Unknown argument {other:?}")),
+        };
+    }
+
+    Ok(command)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn runs_without_arguments() {
+        assert_eq!(parse_args(args(&[])).unwrap(), Command::Run);
+    }
+
+    #[test]
+    fn parses_commands() {
+        assert_eq!(parse_args(args(&["--prefetch"])).unwrap(), Command::Prefetch);
+        assert_eq!(parse_args(args(&["--prefetch", "--help"])).unwrap(), Command::Help);
+        assert!(parse_args(args(&["--prefech"])).is_err());
+    }
+}
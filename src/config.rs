@@ -1,10 +1,398 @@
 use serde::{Serialize, Deserialize};
 use anyhow::{Result, anyhow, Context};
-use std::{fs, path::{Path, PathBuf}};
+use std::{collections::HashMap, fs, path::{Path, PathBuf}};
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct Configuration {
-    pub discord_client_id: String
+    pub discord_client_id: String,
+    /// How to pick the game to show when several games are running
+    #[serde(default)]
+    pub game_selection: GameSelection,
+    /// Grace periods for games starting and stopping
+    #[serde(default)]
+    pub lifecycle: Lifecycle,
+    /// Detection of games that are left running in the background
+    #[serde(default)]
+    pub idle: Idle,
+    /// Activities shown while no game is being played
+    #[serde(default)]
+    pub activities: Activities,
+    /// How VR sessions are shown
+    #[serde(default)]
+    pub vr: Vr,
+    /// How Steam Remote Play sessions are shown
+    #[serde(default)]
+    pub remote_play: RemotePlay,
+    /// Where the names and images of apps come from
+    #[serde(default)]
+    pub metadata: Metadata,
+    /// How to answer the age checks of the Steam Store
+    #[serde(default)]
+    pub age_gate: AgeGate,
+    /// The language and country of the Steam Store
+    #[serde(default)]
+    pub store: Store,
+    /// Timeouts, retries and rate limiting of the requests to Steam and the other metadata providers
+    #[serde(default)]
+    pub http: Http,
+    /// Looking up the installed games in the background after starting
+    #[serde(default)]
+    pub prefetch: Prefetch,
+    /// Where the metadata cache is kept, and how much of it for how long
+    #[serde(default)]
+    pub cache: Cache,
+    /// Deprecated alias of [Cache::location]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_location: Option<String>,
+}
+
+impl Configuration {
+    /// The configured cache location, from [Cache::location] or the deprecated `cache_location`
+    pub fn cache_location(&self) -> Option<&str> {
+        self.cache.location.as_deref().or(self.cache_location.as_deref())
+    }
+}
+
+/// Settings for the metadata cache
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Cache {
+    /// Base directory for the metadata cache, which is kept in its `discord-rpc-helper` subdirectory.
+    /// Created if it does not exist. Defaults to `$XDG_CACHE_HOME`.
+    pub location: Option<String>,
+    /// How long cached metadata is used before it is revalidated
+    pub ttl_hours: u64,
+    /// How many apps the cache keeps before the least recently used ones are evicted
+    pub max_entries: usize,
+    /// How large the cache may grow in KiB before the least recently used apps are evicted
+    pub max_size_kb: u64,
+}
+
+impl Default for Cache {
+    fn default() -> Self {
+        Self {
+            location: None,
+            ttl_hours: 7 * 24,
+            max_entries: 5000,
+            max_size_kb: 4096,
+        }
+    }
+}
+
+/// Settings for warming the metadata cache with the installed games
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Prefetch {
+    pub enabled: bool,
+    /// Pause after each game that had to be downloaded, so lookups of running games go first
+    pub delay_ms: u64,
+}
+
+impl Default for Prefetch {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            delay_ms: 10_000,
+        }
+    }
+}
+
+/// Settings for the requests to the Steam Store and the other metadata providers
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Http {
+    /// How long to wait for a connection to the server
+    pub connect_timeout_secs: u64,
+    /// How long to wait for the server to send more data before the request is given up
+    pub read_timeout_secs: u64,
+    /// How often a request is retried after timeouts, connection errors, `429 Too Many Requests` and server errors
+    pub max_retries: u32,
+    /// Delay before the first retry. It doubles with every further retry, with some random jitter.
+    pub retry_delay_ms: u64,
+    /// The most requests sent per minute, over all providers. 0 turns the limit off.
+    pub requests_per_minute: u32,
+    /// Sent as `User-Agent`. Defaults to the name and version of this app.
+    pub user_agent: Option<String>,
+    /// Proxy for all requests, like `http://proxy:3128` or `socks5h://localhost:1080`.
+    /// Without it, the proxy from `HTTPS_PROXY`, `HTTP_PROXY` or `ALL_PROXY` is used.
+    pub proxy: Option<String>,
+    /// Comma-separated hosts that are reached without [Http::proxy]. Defaults to `NO_PROXY`.
+    pub no_proxy: Option<String>,
+    /// PEM file with CA certificates to trust in addition to the system ones, e.g. of a proxy that intercepts TLS
+    pub ca_bundle: Option<String>,
+}
+
+impl Default for Http {
+    fn default() -> Self {
+        Self {
+            connect_timeout_secs: 10,
+            read_timeout_secs: 30,
+            max_retries: 3,
+            retry_delay_ms: 1000,
+            requests_per_minute: 30,
+            user_agent: None,
+            proxy: None,
+            no_proxy: None,
+            ca_bundle: None,
+        }
+    }
+}
+
+/// Settings for the language of game names from the Steam Store
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct Store {
+    /// The language as Steam calls it: `english`, `german`, `schinese`, ... Defaults to the store's guess from your IP address.
+    /// Names that are not translated are shown in English.
+    pub language: Option<String>,
+    /// The two-letter country code of the store, like `DE`. Some games are only on the store in some countries.
+    pub country: Option<String>,
+}
+
+/// Settings for the age checks of the Steam Store
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct AgeGate {
+    pub policy: AgeGatePolicy,
+    /// The birthday to answer with, as `YYYY-MM-DD`
+    pub birthday: String,
+    /// The `steamLoginSecure` cookie of a logged-in Steam session, for the `session` policy
+    pub session_cookie: Option<String>,
+}
+
+impl Default for AgeGate {
+    fn default() -> Self {
+        Self {
+            policy: AgeGatePolicy::Birthday,
+            birthday: "1990-01-01".to_owned(),
+            session_cookie: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AgeGatePolicy {
+    /// Never answer age checks. Age gated games are looked up with the other metadata providers.
+    Never,
+    /// Answer age checks with [AgeGate::birthday]
+    #[default]
+    Birthday,
+    /// Use the session from [AgeGate::session_cookie]
+    Session,
+}
+
+/// Settings for looking up the names and images of apps
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Metadata {
+    /// The providers to ask, in order. Each field is taken from the first provider that has it.
+    pub providers: Vec<MetadataProviderKind>,
+    /// Fields to use instead of the looked up ones, by AppId
+    pub overrides: HashMap<u32, MetadataOverride>,
+    /// API key for SteamGridDB. Without a key, SteamGridDB is skipped.
+    pub steamgriddb_api_key: Option<String>,
+}
+
+impl Default for Metadata {
+    fn default() -> Self {
+        Self {
+            providers: vec![
+                MetadataProviderKind::Overrides,
+                MetadataProviderKind::StoreApi,
+                MetadataProviderKind::Local,
+                // scraping the store page is only a fallback
+                MetadataProviderKind::StorePage,
+                MetadataProviderKind::Steamgriddb,
+            ],
+            overrides: HashMap::new(),
+            steamgriddb_api_key: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MetadataProviderKind {
+    /// The `overrides` from the configuration
+    Overrides,
+    /// The Steam client's files: app manifests and `appinfo.vdf`
+    Local,
+    /// The Steam Store `appdetails` API
+    StoreApi,
+    /// The scraped Steam Store page
+    StorePage,
+    /// Community artwork from SteamGridDB
+    Steamgriddb,
+}
+
+/// Metadata of an app set in the configuration
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct MetadataOverride {
+    pub name: Option<String>,
+    pub icon_url: Option<String>,
+    pub image_url: Option<String>,
+}
+
+/// Settings for games streamed with Steam Remote Play
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct RemotePlay {
+    pub enabled: bool,
+    /// Added to the activity state while the game is streamed
+    pub state_text: String,
+    /// Added to the activity state during a Remote Play Together session
+    pub together_text: String,
+    /// The party size shown for Remote Play Together sessions
+    pub together_max_players: u32,
+}
+
+impl Default for RemotePlay {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            state_text: "Streaming via Remote Play".to_owned(),
+            together_text: "Remote Play Together".to_owned(),
+            together_max_players: 4,
+        }
+    }
+}
+
+/// Settings for VR sessions (SteamVR, Monado, WiVRn)
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Vr {
+    pub enabled: bool,
+    /// Added to the activity state while a VR runtime is running
+    pub state_text: String,
+    /// Key of the small image shown while in VR. The image has to be uploaded to your Discord application.
+    pub small_image: Option<String>,
+    /// Show an activity when the VR runtime runs without a game
+    pub home: bool,
+}
+
+impl Default for Vr {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            state_text: "In VR".to_owned(),
+            small_image: None,
+            home: true,
+        }
+    }
+}
+
+/// Switches for the activities that are shown around playing a game
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Activities {
+    /// Show "Compiling shaders for <game>" while Steam pre-compiles shaders
+    pub shader_compilation: bool,
+    /// Show "Updating <game>" with the download progress while Steam updates a game
+    pub downloads: bool,
+    /// Show an activity while Steam is open, but no game is running
+    pub library: bool,
+    /// The text of the library activity
+    pub library_text: String,
+    /// The text of the library activity when Steam runs in Big Picture mode
+    pub big_picture_text: String,
+}
+
+impl Default for Activities {
+    fn default() -> Self {
+        Self {
+            shader_compilation: true,
+            downloads: false,
+            library: false,
+            library_text: "Browsing the Steam library".to_owned(),
+            big_picture_text: "In Big Picture mode".to_owned(),
+        }
+    }
+}
+
+/// Settings for choosing among several running games
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct GameSelection {
+    pub policy: SelectionPolicy,
+    /// AppIds in order of preference, used by [SelectionPolicy::Priority]
+    pub priority: Vec<u32>,
+    /// Mention the number of other running games in the activity, e.g. "Playing X (+1 more)"
+    pub show_other_games: bool,
+}
+
+/// Grace periods used to debounce games starting and stopping
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Lifecycle {
+    /// How long a game has to be running before it is shown
+    pub launch_grace_secs: u64,
+    /// How long a game is still shown after it disappeared. If it comes back in time, the elapsed time is kept.
+    pub exit_grace_secs: u64,
+}
+
+impl Default for Lifecycle {
+    fn default() -> Self {
+        Self {
+            launch_grace_secs: 0,
+            exit_grace_secs: 30,
+        }
+    }
+}
+
+/// Settings for detecting games that are running, but not being played
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Idle {
+    pub enabled: bool,
+    /// How long a game has to be idle before the activity changes
+    pub threshold_secs: u64,
+    /// CPU usage (in percent of one core) below which a game counts as idle
+    pub cpu_threshold: f32,
+    /// Also treat the game as idle when logind reports the session as idle
+    pub use_logind: bool,
+    pub action: IdleAction,
+    /// The activity state shown with [IdleAction::ShowIdle]
+    pub state_text: String,
+}
+
+impl Default for Idle {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            threshold_secs: 900,
+            cpu_threshold: 2.0,
+            use_logind: false,
+            action: IdleAction::ShowIdle,
+            state_text: "Idle".to_owned(),
+        }
+    }
+}
+
+/// What to do with the activity when the game is idle
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum IdleAction {
+    /// Keep the activity and set its state to [Idle::state_text]
+    #[default]
+    ShowIdle,
+    /// Clear the activity until the game is played again
+    Clear,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SelectionPolicy {
+    /// The game that was started last
+    #[default]
+    MostRecent,
+    /// The game that has been running the longest
+    LongestRunning,
+    /// The game that used the most CPU since the last scan
+    HighestCpu,
+    /// The first game from the priority list. Falls back to the most recent game.
+    Priority,
 }
 
 
@@ -41,6 +429,12 @@ impl Configuration {
             errors.push("discord_client_id is empty.".to_owned());
         }
 
+        if self.game_selection.policy == SelectionPolicy::Priority && self.game_selection.priority.is_empty() {
+            errors.push("game_selection.policy is priority, but game_selection.priority is empty.".to_owned());
+        }
+
+        errors.extend(crate::steam::config_errors(self));
+
         errors
     }
 }
@@ -68,12 +462,13 @@ mod tests {
 
     use anyhow::anyhow;
 
-    use super::{get_config_path, Configuration};
+    use super::{get_config_path, Configuration, IdleAction, MetadataProviderKind, SelectionPolicy};
 
     #[test]
     fn detects_invalid_config() {
         let config = Configuration {
-            discord_client_id: "".to_string()
+            discord_client_id: "".to_string(),
+            ..Default::default()
         };
 
         let validation_result = config.validate();
@@ -81,6 +476,33 @@ mod tests {
         assert!(!validation_result.is_empty());
     }
     
+    #[test]
+    fn reports_all_invalid_settings() {
+        let config = super::from_string(r#"
+            {
+                "discord_client_id": "5456",
+                "game_selection": { "policy": "priority" },
+                "age_gate": { "birthday": "1990-02-30" },
+                "store": { "country": "DEU" },
+                "http": { "proxy": "ftp://proxy.example.com", "ca_bundle": "/nonexistent/ca.pem" }
+            }
+        "#).unwrap();
+
+        let errors = config.validate();
+
+        assert_eq!(errors.len(), 5, "{errors:?}");
+        assert!(errors.iter().any(|e| e.contains("age_gate.birthday")));
+        assert!(errors.iter().any(|e| e.contains("store.country")));
+        assert!(Configuration { discord_client_id: "5456".to_owned(), ..Default::default() }.validate().is_empty());
+    }
+
+    #[test]
+    fn requires_session_cookie_for_session_policy() {
+        let config = super::from_string(r#"{ "discord_client_id": "5456", "age_gate": { "policy": "session" } }"#).unwrap();
+
+        assert_eq!(config.validate().len(), 1);
+    }
+
     #[test]
     fn can_find_config_file() {
         let config_path = get_config_path();
@@ -131,4 +553,113 @@ mod tests {
 
         assert!(config.discord_client_id == client_id);
     }
+
+    #[test]
+    fn defaults_to_most_recent_game() {
+        let config = super::from_string(r#"{ "discord_client_id": "5456" }"#).unwrap();
+
+        assert_eq!(config.game_selection.policy, SelectionPolicy::MostRecent);
+        assert!(!config.game_selection.show_other_games);
+    }
+
+    #[test]
+    fn can_read_cache_section() {
+        let config = super::from_string(r#"{ "discord_client_id": "5456", "cache": { "location": "/tmp", "ttl_hours": 24 } }"#).unwrap();
+
+        assert_eq!(config.cache_location(), Some("/tmp"));
+        assert_eq!(config.cache.ttl_hours, 24);
+        assert_eq!(config.cache.max_entries, 5000);
+    }
+
+    #[test]
+    fn still_reads_deprecated_cache_location() {
+        let config = super::from_string(r#"{ "discord_client_id": "5456", "cache_location": "/tmp" }"#).unwrap();
+
+        assert_eq!(config.cache_location(), Some("/tmp"));
+    }
+
+    #[test]
+    fn can_read_game_selection() {
+        let config_str = r#"
+            {
+                "discord_client_id": "5456",
+                "game_selection": {
+                    "policy": "priority",
+                    "priority": [570, 730]
+                }
+            }
+        "#;
+
+        let config = super::from_string(config_str).unwrap();
+
+        assert_eq!(config.game_selection.policy, SelectionPolicy::Priority);
+        assert_eq!(config.game_selection.priority, vec![570, 730]);
+    }
+
+    #[test]
+    fn can_read_partial_lifecycle() {
+        let config_str = r#"
+            {
+                "discord_client_id": "5456",
+                "lifecycle": { "launch_grace_secs": 15 }
+            }
+        "#;
+
+        let config = super::from_string(config_str).unwrap();
+
+        assert_eq!(config.lifecycle.launch_grace_secs, 15);
+        assert_eq!(config.lifecycle.exit_grace_secs, 30);
+    }
+
+    #[test]
+    fn can_read_idle_action() {
+        let config_str = r#"
+            {
+                "discord_client_id": "5456",
+                "idle": { "enabled": true, "action": "clear" }
+            }
+        "#;
+
+        let config = super::from_string(config_str).unwrap();
+
+        assert!(config.idle.enabled);
+        assert_eq!(config.idle.action, IdleAction::Clear);
+        assert_eq!(config.idle.state_text, "Idle");
+    }
+
+    #[test]
+    fn can_read_metadata_overrides() {
+        let config_str = r#"
+            {
+                "discord_client_id": "5456",
+                "metadata": {
+                    "providers": ["overrides", "local"],
+                    "overrides": { "570": { "name": "Dota" } }
+                }
+            }
+        "#;
+
+        let config = super::from_string(config_str).unwrap();
+
+        assert_eq!(config.metadata.providers, vec![MetadataProviderKind::Overrides, MetadataProviderKind::Local]);
+        assert_eq!(config.metadata.overrides[&570].name.as_deref(), Some("Dota"));
+        assert!(config.metadata.overrides[&570].icon_url.is_none());
+    }
+
+    #[test]
+    fn can_read_partial_http() {
+        let config_str = r#"
+            {
+                "discord_client_id": "5456",
+                "http": { "requests_per_minute": 10, "user_agent": "my-helper" }
+            }
+        "#;
+
+        let config = super::from_string(config_str).unwrap();
+
+        assert_eq!(config.http.requests_per_minute, 10);
+        assert_eq!(config.http.user_agent.as_deref(), Some("my-helper"));
+        assert_eq!(config.http.max_retries, 3);
+        assert!(config.http.proxy.is_none());
+    }
 }
\ No newline at end of file
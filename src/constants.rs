@@ -1,4 +1,6 @@
 pub const APPID_ENV_KEY: &str = "SteamAppId";
+pub const APPID_CMD_KEY: &str = "AppId=";
 pub const NO_APPID: u32 = 0;
-pub const STEAM_GAME_PATH_FRAGMENT: &str = "Steam/steamapps/common";
+pub const STEAM_GAME_PATH_FRAGMENT: &str = "steamapps/common";
+pub const STEAM_SHADER_CACHE_PATH_FRAGMENT: &str = "steamapps/shadercache/";
 pub const APP_NAME: &str = "discord-rpc-helper";
\ No newline at end of file
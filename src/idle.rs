@@ -0,0 +1,213 @@
+use crate::config::Idle as IdleConfig;
+use std::{
+    os::unix::fs::MetadataExt,
+    time::{Duration, Instant},
+};
+use tokio::process::Command;
+use tracing::debug;
+
+/// Decides if a running game is idle, based on its CPU usage and optionally the session's idle hint.
+///
+/// A scan counts as idle when the game's CPU usage is below `cpu_threshold` or when logind reports
+/// the session as idle. Once the game has been idle for `threshold_secs`, it is considered idle
+/// until the next active scan.
+pub struct IdleTracker {
+    threshold: i64,
+    cpu_threshold: f32,
+    app_id: Option<u32>,
+    last_active: i64,
+}
+
+impl IdleTracker {
+    pub fn new(config: &IdleConfig) -> Self {
+        Self {
+            threshold: config.threshold_secs as i64,
+            cpu_threshold: config.cpu_threshold,
+            app_id: None,
+            last_active: 0,
+        }
+    }
+
+    /// Feeds a scan of the shown game into the tracker and returns whether the game is idle.
+    ///
+    /// * `cpu_usage`: CPU usage of the game since the previous scan, or `None` if it is not in the scan
+    /// * `session_idle`: logind's `IdleHint`, if available
+    /// * `now`: the current unix timestamp in seconds
+    pub fn observe(&mut self, app_id: u32, cpu_usage: Option<f32>, session_idle: Option<bool>, now: i64) -> bool {
+        if self.app_id != Some(app_id) {
+            self.app_id = Some(app_id);
+            self.last_active = now;
+        }
+
+        let busy = cpu_usage.is_some_and(|usage| usage >= self.cpu_threshold);
+
+        if busy && session_idle != Some(true) {
+            self.last_active = now;
+        }
+
+        now - self.last_active >= self.threshold
+    }
+
+    /// Forgets the tracked game
+    pub fn reset(&mut self) {
+        self.app_id = None;
+    }
+}
+
+/// How long logind's idle hint is reused before asking again
+const IDLE_HINT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// logind's idle hint for this process' session, queried at most once per [`IDLE_HINT_INTERVAL`]
+#[derive(Default)]
+pub struct SessionIdleHint {
+    last: Option<(Instant, Option<bool>)>,
+}
+
+impl SessionIdleHint {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the idle hint, asking logind again if the last answer is too old
+    pub async fn get(&mut self) -> Option<bool> {
+        match self.last {
+            Some((checked_at, hint)) if checked_at.elapsed() < IDLE_HINT_INTERVAL => hint,
+            _ => {
+                let hint = session_idle_hint().await;
+                self.last = Some((Instant::now(), hint));
+                hint
+            }
+        }
+    }
+}
+
+/// Asks logind if the session this process runs in is idle.
+///
+/// Without `XDG_SESSION_ID`, e.g. when running as a systemd user service, the current user's idle hint is used,
+/// which is set when all of the user's sessions are idle. Returns `None` if logind could not be queried.
+async fn session_idle_hint() -> Option<bool> {
+    let session_id = std::env::var("XDG_SESSION_ID").ok();
+    let uid = match std::fs::metadata("/proc/self") {
+        Ok(m) => m.uid(),
+        Err(e) => {
+            debug!("Could not get the user id: {e}");
+            return None;
+        }
+    };
+
+    let output = Command::new("loginctl")
+        .args(idle_hint_args(session_id.as_deref(), uid))
+        .output()
+        .await;
+
+    match output {
+        Ok(o) if o.status.success() => parse_idle_hint(&String::from_utf8_lossy(&o.stdout)),
+        Ok(o) => {
+            debug!("loginctl exited with {}", o.status);
+            None
+        }
+        Err(e) => {
+            debug!("Could not run loginctl: {e}");
+            None
+        }
+    }
+}
+
+/// The `loginctl` arguments to get the idle hint of a session, or of a user if the session is not known
+fn idle_hint_args(session_id: Option<&str>, uid: u32) -> Vec<String> {
+    let object = match session_id.filter(|id| !id.is_empty()) {
+        Some(id) => ["show-session".to_owned(), id.to_owned()],
+        None => ["show-user".to_owned(), uid.to_string()],
+    };
+
+    object
+        .into_iter()
+        .chain(["--property=IdleHint".to_owned(), "--value".to_owned()])
+        .collect()
+}
+
+fn parse_idle_hint(value: &str) -> Option<bool> {
+    match value.trim() {
+        "yes" => Some(true),
+        "no" => Some(false),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    const GAME: u32 = 570;
+
+    fn tracker() -> IdleTracker {
+        IdleTracker::new(&IdleConfig {
+            enabled: true,
+            threshold_secs: 60,
+            cpu_threshold: 5.0,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn busy_game_is_not_idle() {
+        let mut tracker = tracker();
+
+        let idle: Vec<bool> = (0..10)
+            .map(|t| tracker.observe(GAME, Some(50.0), None, t * 10))
+            .collect();
+
+        assert!(idle.iter().all(|i| !i));
+    }
+
+    #[test]
+    fn quiet_game_becomes_idle_after_threshold() {
+        let mut tracker = tracker();
+
+        assert!(!tracker.observe(GAME, Some(50.0), None, 0));
+        assert!(!tracker.observe(GAME, Some(0.5), None, 30));
+        assert!(tracker.observe(GAME, Some(0.5), None, 60));
+    }
+
+    #[test]
+    fn becomes_active_again() {
+        let mut tracker = tracker();
+
+        tracker.observe(GAME, Some(0.5), None, 0);
+        assert!(tracker.observe(GAME, Some(0.5), None, 70));
+        assert!(!tracker.observe(GAME, Some(50.0), None, 80));
+    }
+
+    #[test]
+    fn idle_session_overrides_cpu_usage() {
+        let mut tracker = tracker();
+
+        tracker.observe(GAME, Some(50.0), Some(true), 0);
+
+        assert!(tracker.observe(GAME, Some(50.0), Some(true), 60));
+    }
+
+    #[test]
+    fn new_game_starts_active() {
+        let mut tracker = tracker();
+
+        tracker.observe(GAME, Some(0.5), None, 0);
+        assert!(tracker.observe(GAME, Some(0.5), None, 60));
+        assert!(!tracker.observe(730, Some(0.5), None, 70));
+    }
+
+    #[test]
+    fn asks_for_own_session() {
+        assert_eq!(idle_hint_args(Some("3"), 1000), ["show-session", "3", "--property=IdleHint", "--value"]);
+        assert_eq!(idle_hint_args(None, 1000), ["show-user", "1000", "--property=IdleHint", "--value"]);
+        assert_eq!(idle_hint_args(Some(""), 1000), ["show-user", "1000", "--property=IdleHint", "--value"]);
+    }
+
+    #[test]
+    fn parses_idle_hint() {
+        assert_eq!(parse_idle_hint("yes\n"), Some(true));
+        assert_eq!(parse_idle_hint("no\n"), Some(false));
+        assert_eq!(parse_idle_hint(""), None);
+    }
+}
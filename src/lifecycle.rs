@@ -0,0 +1,256 @@
+use crate::config::Lifecycle as LifecycleConfig;
+
+/// The game that should currently be shown as activity
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ActiveGame {
+    pub app_id: u32,
+    /// Unix timestamp (seconds) of when the game was first seen starting
+    pub started_at: i64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GameState {
+    /// No game is running
+    Idle,
+    /// A game was seen, but not for long enough to show it yet. Until then, the game shown before stays shown.
+    Launching { game: ActiveGame, seen_at: i64, shown: Option<ActiveGame> },
+    /// A game is running and shown
+    Running { game: ActiveGame },
+    /// The shown game disappeared, but might come back before the grace period is over
+    Exiting { game: ActiveGame, gone_at: i64 },
+}
+
+/// Debounces the games seen by the scanner into a stable activity.
+///
+/// * A game has to be seen for `launch_grace_secs` before it is shown.
+/// * A game that disappears is kept for `exit_grace_secs`. If it comes back within that time, it keeps its original start time.
+pub struct GameLifecycle {
+    state: GameState,
+    launch_grace: i64,
+    exit_grace: i64,
+}
+
+impl GameLifecycle {
+    pub fn new(config: &LifecycleConfig) -> Self {
+        Self {
+            state: GameState::Idle,
+            launch_grace: config.launch_grace_secs as i64,
+            exit_grace: config.exit_grace_secs as i64,
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn state(&self) -> GameState {
+        self.state
+    }
+
+    /// Feeds the result of a scan into the state machine.
+    ///
+    /// * `seen`: the selected running game as `(app_id, running_since)`, if any
+    /// * `now`: the current unix timestamp in seconds
+    ///
+    /// Returns the game that should be shown after this scan.
+    pub fn observe(&mut self, seen: Option<(u32, i64)>, now: i64) -> Option<ActiveGame> {
+        self.state = match (self.state, seen) {
+            (GameState::Idle, None) => GameState::Idle,
+            (GameState::Idle, Some((app_id, running_since))) => self.launch(ActiveGame { app_id, started_at: running_since }, None, now),
+
+            (GameState::Launching { shown: None, .. }, None) => GameState::Idle,
+            (GameState::Launching { shown: Some(shown), .. }, None) => self.exit(shown, now, now),
+            (GameState::Launching { game, seen_at, shown }, Some((app_id, running_since))) if game.app_id == app_id => {
+                let game = ActiveGame { app_id, started_at: game.started_at.min(running_since) };
+                match now - seen_at >= self.launch_grace {
+                    true => GameState::Running { game },
+                    false => GameState::Launching { game, seen_at, shown },
+                }
+            }
+            // the shown game is selected again before the other one launched
+            (GameState::Launching { shown: Some(shown), .. }, Some((app_id, _))) if shown.app_id == app_id => GameState::Running { game: shown },
+            (GameState::Launching { shown, .. }, Some((app_id, running_since))) => self.launch(ActiveGame { app_id, started_at: running_since }, shown, now),
+
+            (GameState::Running { game }, None) => self.exit(game, now, now),
+            (GameState::Running { game }, Some((app_id, _))) if game.app_id == app_id => GameState::Running { game },
+            (GameState::Running { game }, Some((app_id, running_since))) => self.launch(ActiveGame { app_id, started_at: running_since }, Some(game), now),
+
+            (GameState::Exiting { game, gone_at }, None) => self.exit(game, gone_at, now),
+            (GameState::Exiting { game, .. }, Some((app_id, _))) if game.app_id == app_id => GameState::Running { game },
+            (GameState::Exiting { game, .. }, Some((app_id, running_since))) => self.launch(ActiveGame { app_id, started_at: running_since }, Some(game), now),
+        };
+
+        self.active()
+    }
+
+    /// The game that should be shown in the current state
+    pub fn active(&self) -> Option<ActiveGame> {
+        match self.state {
+            GameState::Running { game } | GameState::Exiting { game, .. } => Some(game),
+            GameState::Launching { shown, .. } => shown,
+            GameState::Idle => None,
+        }
+    }
+
+    /// Starts showing a newly seen game, after the launch grace period. `shown` stays shown until then.
+    fn launch(&self, game: ActiveGame, shown: Option<ActiveGame>, now: i64) -> GameState {
+        match self.launch_grace {
+            0 => GameState::Running { game },
+            _ => GameState::Launching { game, seen_at: now, shown },
+        }
+    }
+
+    fn exit(&self, game: ActiveGame, gone_at: i64, now: i64) -> GameState {
+        match now - gone_at >= self.exit_grace {
+            true => GameState::Idle,
+            false => GameState::Exiting { game, gone_at },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    const GAME: u32 = 570;
+    const OTHER_GAME: u32 = 730;
+
+    fn lifecycle(launch_grace_secs: u64, exit_grace_secs: u64) -> GameLifecycle {
+        GameLifecycle::new(&LifecycleConfig { launch_grace_secs, exit_grace_secs })
+    }
+
+    /// Runs a timeline of `(now, seen)` scans and returns the shown game after each scan
+    fn run(lifecycle: &mut GameLifecycle, timeline: &[(i64, Option<(u32, i64)>)]) -> Vec<Option<ActiveGame>> {
+        timeline
+            .iter()
+            .map(|(now, seen)| lifecycle.observe(*seen, *now))
+            .collect()
+    }
+
+    fn shown(app_id: u32, started_at: i64) -> Option<ActiveGame> {
+        Some(ActiveGame { app_id, started_at })
+    }
+
+    #[test]
+    fn shows_game_immediately_without_launch_grace() {
+        let mut lifecycle = lifecycle(0, 0);
+
+        let result = run(&mut lifecycle, &[(0, None), (10, Some((GAME, 5))), (20, None)]);
+
+        assert_eq!(result, vec![None, shown(GAME, 5), None]);
+    }
+
+    #[test]
+    fn waits_for_launch_grace() {
+        let mut lifecycle = lifecycle(15, 30);
+
+        let result = run(&mut lifecycle, &[(10, Some((GAME, 8))), (20, Some((GAME, 8))), (30, Some((GAME, 8)))]);
+
+        assert_eq!(result, vec![None, None, shown(GAME, 8)]);
+        assert_eq!(lifecycle.state(), GameState::Running { game: ActiveGame { app_id: GAME, started_at: 8 } });
+    }
+
+    #[test]
+    fn ignores_short_lived_launches() {
+        let mut lifecycle = lifecycle(15, 30);
+
+        let result = run(&mut lifecycle, &[(10, Some((GAME, 8))), (20, None), (30, None)]);
+
+        assert_eq!(result, vec![None, None, None]);
+        assert_eq!(lifecycle.state(), GameState::Idle);
+    }
+
+    #[test]
+    fn keeps_start_time_across_short_gaps() {
+        let mut lifecycle = lifecycle(0, 30);
+
+        let result = run(&mut lifecycle, &[
+            (10, Some((GAME, 5))),
+            (20, None),
+            (30, Some((GAME, 25))), // the game restarted itself
+            (40, Some((GAME, 25))),
+        ]);
+
+        assert_eq!(result, vec![shown(GAME, 5); 4]);
+    }
+
+    #[test]
+    fn clears_after_exit_grace() {
+        let mut lifecycle = lifecycle(0, 30);
+
+        let result = run(&mut lifecycle, &[(10, Some((GAME, 5))), (20, None), (40, None), (50, None)]);
+
+        assert_eq!(result, vec![shown(GAME, 5), shown(GAME, 5), shown(GAME, 5), None]);
+        assert_eq!(lifecycle.state(), GameState::Idle);
+    }
+
+    #[test]
+    fn restarts_timer_after_exit_grace() {
+        let mut lifecycle = lifecycle(0, 10);
+
+        let result = run(&mut lifecycle, &[(10, Some((GAME, 5))), (20, None), (30, None), (40, Some((GAME, 35)))]);
+
+        assert_eq!(result, vec![shown(GAME, 5), shown(GAME, 5), None, shown(GAME, 35)]);
+    }
+
+    #[test]
+    fn switches_to_other_game() {
+        let mut lifecycle = lifecycle(0, 30);
+
+        let result = run(&mut lifecycle, &[(10, Some((GAME, 5))), (20, None), (30, Some((OTHER_GAME, 25)))]);
+
+        assert_eq!(result, vec![shown(GAME, 5), shown(GAME, 5), shown(OTHER_GAME, 25)]);
+    }
+
+    #[test]
+    fn waits_for_launch_grace_of_other_game() {
+        let mut lifecycle = lifecycle(15, 30);
+
+        let result = run(&mut lifecycle, &[
+            (0, Some((GAME, 0))),
+            (20, Some((GAME, 0))),
+            (30, Some((OTHER_GAME, 28))),
+            (40, Some((OTHER_GAME, 28))),
+            (50, Some((OTHER_GAME, 28))),
+        ]);
+
+        assert_eq!(result, vec![None, shown(GAME, 0), shown(GAME, 0), shown(GAME, 0), shown(OTHER_GAME, 28)]);
+    }
+
+    #[test]
+    fn keeps_game_when_other_game_is_short_lived() {
+        let mut lifecycle = lifecycle(15, 30);
+
+        let result = run(&mut lifecycle, &[
+            (0, Some((GAME, 0))),
+            (20, Some((GAME, 0))),
+            (30, Some((OTHER_GAME, 28))),
+            (40, Some((GAME, 0))),
+            (60, Some((GAME, 0))),
+        ]);
+
+        assert_eq!(result, vec![None, shown(GAME, 0), shown(GAME, 0), shown(GAME, 0), shown(GAME, 0)]);
+        assert_eq!(lifecycle.state(), GameState::Running { game: ActiveGame { app_id: GAME, started_at: 0 } });
+    }
+
+    #[test]
+    fn exits_shown_game_while_other_game_launches() {
+        let mut lifecycle = lifecycle(15, 30);
+
+        let result = run(&mut lifecycle, &[(0, Some((GAME, 0))), (20, Some((GAME, 0))), (30, Some((OTHER_GAME, 28))), (40, None), (80, None)]);
+
+        assert_eq!(result, vec![None, shown(GAME, 0), shown(GAME, 0), shown(GAME, 0), None]);
+    }
+
+    #[test]
+    fn restarts_launch_for_other_game() {
+        let mut lifecycle = lifecycle(15, 30);
+
+        let result = run(&mut lifecycle, &[
+            (10, Some((GAME, 5))),
+            (20, Some((OTHER_GAME, 18))),
+            (30, Some((OTHER_GAME, 18))),
+            (40, Some((OTHER_GAME, 18))),
+        ]);
+
+        assert_eq!(result, vec![None, None, None, shown(OTHER_GAME, 18)]);
+    }
+}
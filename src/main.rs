@@ -1,18 +1,26 @@
+mod cli;
 mod config;
 mod steam;
 mod constants;
+mod selection;
+mod lifecycle;
+mod idle;
+mod presence;
 
 use anyhow::{ anyhow, bail, Result };
-use config::Configuration;
-use std::{ borrow::BorrowMut, time::Duration };
-use steam::{ scanner::get_running_steam_games, SteamApp };
+use cli::Command;
+use config::{ Configuration, IdleAction };
+use idle::{IdleTracker, SessionIdleHint};
+use lifecycle::GameLifecycle;
+use presence::PresenceState;
+use std::{ borrow::BorrowMut, time::{ Duration, SystemTime, UNIX_EPOCH } };
+use steam::{scanner::Scanner, AppName};
 use tokio::{ signal, sync::broadcast::{ self, Receiver } };
 use discord_sdk::{
     Discord,
     DiscordApp,
     Subscriptions,
     wheel::Wheel,
-    activity::ActivityBuilder
 };
 use tracing::{debug, info, error, event, Level};
 
@@ -21,6 +29,19 @@ async fn main() -> Result<()> {
     let (shutdown_send, mut shutdown_recv) = broadcast::channel(5);
     tracing_subscriber::fmt::init();
 
+    let command = match cli::parse_args(std::env::args().skip(1)) {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("{e}\n\n{}", cli::USAGE);
+            return Err(e);
+        }
+    };
+
+    if command == Command::Help {
+        println!("{}", cli::USAGE);
+        return Ok(());
+    }
+
     info!("Reading config.json");
     let config = match Configuration::from_file("config.json") {
         Ok(c) => c,
@@ -30,9 +51,9 @@ async fn main() -> Result<()> {
         }
     };
 
-    match validate_config(&config) {
-        Ok(()) => (),
-        Err(errors) => {
+    // the prefetch does not talk to Discord
+    if command == Command::Run {
+        if let Err(errors) = validate_config(&config) {
             error!("Error loading configuration: {errors:?}");
             return Err(errors);
         }
@@ -40,6 +61,30 @@ async fn main() -> Result<()> {
 
     debug!("Found client id {}", config.discord_client_id);
 
+    if let Err(e) = steam::init_cache(&config) {
+        error!("Error setting up the cache: {e:?}");
+        return Err(e);
+    }
+
+    if let Err(e) = steam::init_metadata(&config) {
+        error!("Error setting up the metadata providers: {e:?}");
+        return Err(e);
+    }
+
+    if command == Command::Prefetch {
+        prefetch_once().await;
+        return Ok(());
+    }
+
+    if config.prefetch.enabled {
+        let delay = Duration::from_millis(config.prefetch.delay_ms);
+
+        tokio::spawn(async move {
+            let summary = steam::prefetch_library(delay, |p| debug!(app_id = p.app_id, "Prefetched {}/{}", p.done, p.total)).await;
+            info!("Prefetched the metadata of {} installed apps, {} needed a download", summary.total, summary.fetched);
+        });
+    }
+
     tokio::spawn(async move {
         let loop_result = detection_loop(shutdown_recv.borrow_mut(), config.clone()).await;
         match loop_result {
@@ -57,6 +102,7 @@ async fn main() -> Result<()> {
         Ok(_) => {
             info!("Received shutdown event. Sending shutdown signals (can take up to 1 minute)");
             shutdown_send.send(())?;
+            steam::save_state();
         }
         Err(e) => bail!("Error catching Ctrl-C signal: {}", e),
     }
@@ -83,45 +129,128 @@ async fn detection_loop(shutdown_recv: &mut Receiver<()>, config: Configuration)
     info!("Connected to Discord");
 
     let sleep_dur = Duration::from_secs(10);
-    let mut running_id = constants::NO_APPID;
+    let mut shown: Option<PresenceState> = None;
+    let mut shown_name = String::new();
+    // when to look up the shown app's name again
+    let mut retry_name_at: Option<i64> = None;
+    let mut scanner = Scanner::new();
+    let mut lifecycle = GameLifecycle::new(&config.lifecycle);
+    let mut idle_tracker = IdleTracker::new(&config.idle);
+    let mut session_idle_hint = SessionIdleHint::new();
 
     event!(Level::INFO, "Starting to monitor for Steam games...");
 
     loop {
-        let running_games = get_games()?;
+        let scan = scanner.scan();
+        let selected = selection::select_game(&scan.games, &config.game_selection, lifecycle.active().map(|g| g.app_id));
+        let now = unix_now();
+
+        let desired = match lifecycle.observe(selected.map(|g| (g.app_id, g.running_since)), now) {
+            None => {
+                idle_tracker.reset();
 
-        match running_games.len() {
-            0 if running_id != constants::NO_APPID => {
-                event!(Level::INFO, "Game no longer running. Clearing activity...");
-                running_id = discord.clear_activity().await.map(|_| constants::NO_APPID)?;
+                let compiling = scan.shader_compilations
+                    .first()
+                    .filter(|_| config.activities.shader_compilation)
+                    .map(|c| PresenceState::CompilingShaders { app_id: c.app_id, started_at: c.running_since });
+
+                // only the Steam client downloads updates
+                let updating = match config.activities.downloads && compiling.is_none() && scan.steam_client.is_some() {
+                    true => tokio::task::spawn_blocking(steam::library::app_manifests)
+                        .await
+                        .unwrap_or_default()
+                        .iter()
+                        .find(|m| m.is_updating())
+                        .map(|m| PresenceState::Updating { app_id: m.app_id, progress: m.download_progress() }),
+                    false => None,
+                };
+
+                let vr_home = scan.vr_session
+                    .filter(|_| config.vr.enabled && config.vr.home)
+                    .map(|v| PresenceState::VrHome { runtime: v.runtime, started_at: v.running_since });
+
+                let browsing = scan.steam_client
+                    .filter(|_| config.activities.library)
+                    .map(|c| PresenceState::BrowsingLibrary { started_at: c.running_since, big_picture: c.big_picture });
+
+                compiling.or(updating).or(vr_home).or(browsing)
+            }
+            Some(active) => {
+                let idle = match config.idle.enabled {
+                    true => {
+                        let session_idle = match config.idle.use_logind {
+                            true => session_idle_hint.get().await,
+                            false => None,
+                        };
+                        // while the game is exiting it is not in the scan anymore
+                        let cpu_usage = scan.games.iter().find(|g| g.app_id == active.app_id).map(|g| g.cpu_usage);
+                        idle_tracker.observe(active.app_id, cpu_usage, session_idle, now)
+                    }
+                    false => false,
+                };
+
+                let others = match config.game_selection.show_other_games {
+                    true => scan.games.iter().filter(|g| g.app_id != active.app_id).count(),
+                    false => 0,
+                };
+
+                match idle && config.idle.action == IdleAction::Clear {
+                    true => None,
+                    false => Some(PresenceState::Playing {
+                        game: active,
+                        others,
+                        idle,
+                        vr: config.vr.enabled && scan.vr_session.is_some(),
+                        streaming: match scan.steam_client.filter(|_| config.remote_play.enabled) {
+                            Some(client) => tokio::task::spawn_blocking(move || steam::streaming::streaming_session(client.running_since))
+                                .await
+                                .unwrap_or_default(),
+                            None => None,
+                        },
+                    }),
+                }
+            }
+        };
+
+        match desired {
+            None if shown.is_some() => {
+                event!(Level::INFO, "Nothing to show anymore. Clearing activity...");
+                shown = discord.clear_activity().await.map(|_| None)?;
             }
-            0 if running_id == constants::NO_APPID => {}
-            _ => {
-                let game = &running_games[0];
-
-                if running_id != game.app_id {
-                    let game_name = game.get_name().await?;
-                    event!(Level::INFO, "Setting activity to game {}", &game_name);
-
-                    running_id = discord
-                        .update_activity(
-                            ActivityBuilder::default()
-                            .start_timestamp(game.running_since)
-                            .details(format!("Playing {game_name:?}"))
-                        )
+            None => {}
+            Some(state) if shown == Some(state) && retry_name_at.is_none_or(|t| now < t) => {}
+            Some(state) => {
+                let app_name = match state.app_id() {
+                    Some(app_id) => steam::get_app_name(app_id).await,
+                    None => AppName::default(),
+                };
+                // stand-in names are looked up again, in case the lookup only failed for the moment
+                retry_name_at = app_name.retry_after.map(|d| now + d.as_secs() as i64);
+
+                if shown == Some(state) && app_name.value == shown_name {
+                    debug!(?state, app_name = %app_name.value, "Looked up the name again, it did not change");
+                } else {
+                    event!(Level::INFO, ?state, app_name = %app_name.value, "Setting activity");
+
+                    shown = discord
+                        .update_activity(state.activity(&app_name.value, &config))
                         .await
                         .map(|res| {
                             if res.is_some() {
-                                game.app_id
+                                Some(state)
                             } else {
                                 error!("Error setting activity");
-                                constants::NO_APPID
+                                None
                             }
                         })?;
+                    shown_name = app_name.value;
                 }
             }
         }
 
+        // downloads of this tick and of the prefetch are written in batches
+        steam::flush_cache().await;
+
         tokio::select! {
             biased;
             _ = tokio::time::sleep(sleep_dur) => {},
@@ -136,11 +265,29 @@ async fn detection_loop(shutdown_recv: &mut Receiver<()>, config: Configuration)
     Ok(())
 }
 
-fn get_games() -> Result<Vec<SteamApp>> {
-    match get_running_steam_games() {
-        Ok(games) => Ok(games),
-        Err(err) => Err(anyhow!("Error trying to find steam games: {}", err)),
-    }
+/// Warms the metadata cache for the installed games and prints the progress.
+///
+/// Runs as fast as the rate limit of the requests allows.
+async fn prefetch_once() {
+    let summary = steam::prefetch_library(Duration::ZERO, |p| {
+        println!("[{}/{}] {}: {}", p.done, p.total, p.app_id, p.name.as_deref().unwrap_or("unknown"));
+    })
+    .await;
+
+    println!(
+        "Looked up {} installed apps, {} needed a download, {} are not known to any metadata provider",
+        summary.total, summary.fetched, summary.unknown
+    );
+
+    steam::save_state();
+}
+
+/// Current unix timestamp in seconds
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default()
 }
 
 fn validate_config(config: &Configuration) -> Result<()> {
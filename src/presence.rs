@@ -0,0 +1,273 @@
+use crate::config::Configuration;
+use crate::lifecycle::ActiveGame;
+use crate::steam::{scanner::VrRuntime, streaming::StreamingSession};
+use discord_sdk::activity::{ActivityBuilder, Assets, PartyPrivacy};
+use std::num::NonZeroU32;
+
+/// Everything the shown activity is built from.
+///
+/// The activity only has to be sent to Discord again when this changes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PresenceState {
+    Playing {
+        game: ActiveGame,
+        /// The number of other running games
+        others: usize,
+        /// The game has not been played for a while
+        idle: bool,
+        /// A VR runtime is running
+        vr: bool,
+        /// The game is streamed with Remote Play
+        streaming: Option<StreamingSession>,
+    },
+    /// Steam is pre-compiling shaders before the game starts
+    CompilingShaders { app_id: u32, started_at: i64 },
+    /// Steam is downloading or applying an update
+    Updating { app_id: u32, progress: Option<u8> },
+    /// Steam is open, but no game is running
+    BrowsingLibrary { started_at: i64, big_picture: bool },
+    /// A VR runtime is running without a game
+    VrHome { runtime: VrRuntime, started_at: i64 },
+}
+
+impl PresenceState {
+    /// The AppId of the app the activity is about, if it is about an app
+    pub fn app_id(&self) -> Option<u32> {
+        match self {
+            PresenceState::Playing { game, .. } => Some(game.app_id),
+            PresenceState::CompilingShaders { app_id, .. } => Some(*app_id),
+            PresenceState::Updating { app_id, .. } => Some(*app_id),
+            PresenceState::BrowsingLibrary { .. } | PresenceState::VrHome { .. } => None,
+        }
+    }
+
+    /// Builds the Discord activity for this state.
+    ///
+    /// `app_name` is ignored for states without an app.
+    pub fn activity(&self, app_name: &str, config: &Configuration) -> ActivityBuilder {
+        match self {
+            PresenceState::Playing { game, others, idle, vr, streaming } => {
+                let details = match others {
+                    0 => format!("Playing {app_name:?}"),
+                    n => format!("Playing {app_name:?} (+{n} more)"),
+                };
+
+                let activity = ActivityBuilder::default()
+                    .start_timestamp(game.started_at)
+                    .details(details);
+
+                let streaming_text = streaming.map(|s| match s.together {
+                    true => config.remote_play.together_text.as_str(),
+                    false => config.remote_play.state_text.as_str(),
+                });
+
+                let state: Vec<&str> = [
+                    idle.then_some(config.idle.state_text.as_str()),
+                    streaming_text,
+                    vr.then_some(config.vr.state_text.as_str()),
+                ]
+                .into_iter()
+                .flatten()
+                .collect();
+
+                let activity = match state.is_empty() {
+                    true => activity,
+                    false => activity.state(state.join(" · ")),
+                };
+
+                // Remote Play Together guests share the host's game, so we show them as a party
+                let activity = match streaming.filter(|s| s.together) {
+                    Some(s) => activity.party(
+                        format!("remote-play-together-{}", game.app_id),
+                        NonZeroU32::new(s.guests + 1),
+                        NonZeroU32::new(config.remote_play.together_max_players.max(s.guests + 1)),
+                        PartyPrivacy::Private,
+                    ),
+                    None => activity,
+                };
+
+                match vr {
+                    true => with_vr_image(activity, config),
+                    false => activity,
+                }
+            }
+            PresenceState::CompilingShaders { started_at, .. } => ActivityBuilder::default()
+                .start_timestamp(*started_at)
+                .details(format!("Compiling shaders for {app_name:?}")),
+            PresenceState::Updating { progress, .. } => ActivityBuilder::default().details(match progress {
+                Some(p) => format!("Updating {app_name:?} — {p}%"),
+                None => format!("Updating {app_name:?}"),
+            }),
+            PresenceState::BrowsingLibrary { started_at, big_picture } => ActivityBuilder::default()
+                .start_timestamp(*started_at)
+                .details(match big_picture {
+                    true => config.activities.big_picture_text.as_str(),
+                    false => config.activities.library_text.as_str(),
+                }),
+            PresenceState::VrHome { runtime, started_at } => {
+                let activity = ActivityBuilder::default()
+                    .start_timestamp(*started_at)
+                    .details(match runtime {
+                        VrRuntime::SteamVr => "SteamVR Home",
+                        VrRuntime::Monado => "In VR (Monado)",
+                        VrRuntime::WiVRn => "In VR (WiVRn)",
+                    });
+
+                with_vr_image(activity, config)
+            }
+        }
+    }
+}
+
+/// Adds the configured VR image as the small image, if there is one
+fn with_vr_image(activity: ActivityBuilder, config: &Configuration) -> ActivityBuilder {
+    match &config.vr.small_image {
+        Some(image) => activity.assets(Assets::default().small(image.as_str(), Some(config.vr.state_text.as_str()))),
+        None => activity,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use discord_sdk::activity::{Activity, ActivityArgs};
+
+    fn render(activity: ActivityBuilder) -> Activity {
+        ActivityArgs::from(activity).activity.unwrap()
+    }
+
+    fn state(others: usize, idle: bool) -> PresenceState {
+        PresenceState::Playing {
+            game: ActiveGame { app_id: 570, started_at: 100 },
+            others,
+            idle,
+            vr: false,
+            streaming: None,
+        }
+    }
+
+    #[test]
+    fn renders_playing_activity() {
+        let activity = render(state(0, false).activity("Dota 2", &Configuration::default()));
+
+        assert_eq!(activity.details.as_deref(), Some("Playing \"Dota 2\""));
+        assert!(activity.state.is_none());
+    }
+
+    #[test]
+    fn renders_other_games() {
+        let activity = render(state(2, false).activity("Dota 2", &Configuration::default()));
+
+        assert_eq!(activity.details.as_deref(), Some("Playing \"Dota 2\" (+2 more)"));
+    }
+
+    #[test]
+    fn renders_idle_state() {
+        let activity = render(state(0, true).activity("Dota 2", &Configuration::default()));
+
+        assert_eq!(activity.state.as_deref(), Some("Idle"));
+    }
+
+    #[test]
+    fn renders_shader_compilation() {
+        let state = PresenceState::CompilingShaders { app_id: 570, started_at: 100 };
+
+        let activity = render(state.activity("Dota 2", &Configuration::default()));
+
+        assert_eq!(state.app_id(), Some(570));
+        assert_eq!(activity.details.as_deref(), Some("Compiling shaders for \"Dota 2\""));
+    }
+
+    #[test]
+    fn renders_update_progress() {
+        let state = PresenceState::Updating { app_id: 570, progress: Some(42) };
+
+        let activity = render(state.activity("Dota 2", &Configuration::default()));
+
+        assert_eq!(activity.details.as_deref(), Some("Updating \"Dota 2\" — 42%"));
+    }
+
+    #[test]
+    fn renders_library_activity() {
+        let state = PresenceState::BrowsingLibrary { started_at: 100, big_picture: false };
+
+        let activity = render(state.activity("", &Configuration::default()));
+
+        assert_eq!(state.app_id(), None);
+        assert_eq!(activity.details.as_deref(), Some("Browsing the Steam library"));
+    }
+
+    #[test]
+    fn renders_big_picture_activity() {
+        let state = PresenceState::BrowsingLibrary { started_at: 100, big_picture: true };
+
+        let activity = render(state.activity("", &Configuration::default()));
+
+        assert_eq!(activity.details.as_deref(), Some("In Big Picture mode"));
+    }
+
+    #[test]
+    fn renders_vr_marker() {
+        let state = PresenceState::Playing {
+            game: ActiveGame { app_id: 620980, started_at: 100 },
+            others: 0,
+            idle: true,
+            vr: true,
+            streaming: None,
+        };
+
+        let activity = render(state.activity("Beat Saber", &Configuration::default()));
+
+        assert_eq!(activity.state.as_deref(), Some("Idle · In VR"));
+        // the image only exists if it was uploaded to the Discord application
+        assert_eq!(activity.assets.and_then(|a| a.small_image), None);
+
+        let mut config = Configuration::default();
+        config.vr.small_image = Some("vr".to_owned());
+        let activity = render(state.activity("Beat Saber", &config));
+
+        assert_eq!(activity.assets.and_then(|a| a.small_image).as_deref(), Some("vr"));
+    }
+
+    #[test]
+    fn renders_steamvr_home() {
+        let state = PresenceState::VrHome { runtime: VrRuntime::SteamVr, started_at: 100 };
+
+        let activity = render(state.activity("", &Configuration::default()));
+
+        assert_eq!(activity.details.as_deref(), Some("SteamVR Home"));
+    }
+
+    #[test]
+    fn renders_remote_play() {
+        let state = PresenceState::Playing {
+            game: ActiveGame { app_id: 570, started_at: 100 },
+            others: 0,
+            idle: false,
+            vr: false,
+            streaming: Some(StreamingSession { together: false, guests: 0 }),
+        };
+
+        let activity = render(state.activity("Dota 2", &Configuration::default()));
+
+        assert_eq!(activity.state.as_deref(), Some("Streaming via Remote Play"));
+        assert!(activity.party.is_none());
+    }
+
+    #[test]
+    fn renders_remote_play_together_party() {
+        let state = PresenceState::Playing {
+            game: ActiveGame { app_id: 570, started_at: 100 },
+            others: 0,
+            idle: false,
+            vr: false,
+            streaming: Some(StreamingSession { together: true, guests: 2 }),
+        };
+
+        let activity = render(state.activity("Dota 2", &Configuration::default()));
+
+        assert_eq!(activity.state.as_deref(), Some("Remote Play Together"));
+        assert_eq!(activity.party.and_then(|p| p.size), Some((3, 4)));
+    }
+}
@@ -0,0 +1,160 @@
+use crate::config::{GameSelection, SelectionPolicy};
+use crate::steam::SteamApp;
+use std::cmp::Ordering;
+
+/// How much more CPU, in percent of one core, another game has to use than the shown one to replace it
+/// with [SelectionPolicy::HighestCpu]. Without it, two busy games would take turns on every scan.
+const CPU_SWITCH_MARGIN: f32 = 20.0;
+
+/// Picks the game to show from the running games, according to the configured policy.
+///
+/// `shown` is the AppId of the game that is currently shown, if any.
+/// Ties are broken in favour of the lower AppId, so the same set of games always results in the same choice.
+pub fn select_game<'a>(games: &'a [SteamApp], selection: &GameSelection, shown: Option<u32>) -> Option<&'a SteamApp> {
+    match selection.policy {
+        SelectionPolicy::MostRecent => most_recent(games),
+        SelectionPolicy::LongestRunning => games.iter().max_by(|a, b| {
+            b.running_since
+                .cmp(&a.running_since)
+                .then_with(|| prefer_lower_app_id(a, b))
+        }),
+        SelectionPolicy::HighestCpu => {
+            let busiest = games.iter().max_by(|a, b| {
+                a.cpu_usage
+                    .total_cmp(&b.cpu_usage)
+                    .then_with(|| prefer_lower_app_id(a, b))
+            })?;
+
+            // the shown game stays, unless the busiest one is clearly busier
+            match games.iter().find(|g| Some(g.app_id) == shown) {
+                Some(current) if busiest.cpu_usage - current.cpu_usage <= CPU_SWITCH_MARGIN => Some(current),
+                _ => Some(busiest),
+            }
+        }
+        SelectionPolicy::Priority => selection
+            .priority
+            .iter()
+            .find_map(|id| games.iter().find(|g| g.app_id == *id))
+            .or_else(|| most_recent(games)),
+    }
+}
+
+fn most_recent(games: &[SteamApp]) -> Option<&SteamApp> {
+    games.iter().max_by(|a, b| {
+        a.running_since
+            .cmp(&b.running_since)
+            .then_with(|| prefer_lower_app_id(a, b))
+    })
+}
+
+/// Orders the game with the lower AppId last, so `max_by` picks it
+fn prefer_lower_app_id(a: &SteamApp, b: &SteamApp) -> Ordering {
+    b.app_id.cmp(&a.app_id)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn game(app_id: u32, running_since: i64, cpu_usage: f32) -> SteamApp {
+        SteamApp {
+            app_id,
+            path: String::new(),
+            running_since,
+            cpu_usage,
+        }
+    }
+
+    fn selection(policy: SelectionPolicy, priority: Vec<u32>) -> GameSelection {
+        GameSelection {
+            policy,
+            priority,
+            ..Default::default()
+        }
+    }
+
+    fn games() -> Vec<SteamApp> {
+        vec![game(10, 200, 5.0), game(20, 100, 90.0), game(30, 300, 1.0)]
+    }
+
+    #[test]
+    fn selects_nothing_without_games() {
+        let selected = select_game(&[], &GameSelection::default(), None);
+
+        assert!(selected.is_none());
+    }
+
+    #[test]
+    fn selects_most_recent_game() {
+        let games = games();
+        let selected = select_game(&games, &selection(SelectionPolicy::MostRecent, vec![]), None);
+
+        assert_eq!(selected.map(|g| g.app_id), Some(30));
+    }
+
+    #[test]
+    fn selects_longest_running_game() {
+        let games = games();
+        let selected = select_game(&games, &selection(SelectionPolicy::LongestRunning, vec![]), None);
+
+        assert_eq!(selected.map(|g| g.app_id), Some(20));
+    }
+
+    #[test]
+    fn selects_busiest_game() {
+        let games = games();
+        let selected = select_game(&games, &selection(SelectionPolicy::HighestCpu, vec![]), None);
+
+        assert_eq!(selected.map(|g| g.app_id), Some(20));
+    }
+
+    #[test]
+    fn selects_by_priority() {
+        let games = games();
+        let selected = select_game(&games, &selection(SelectionPolicy::Priority, vec![99, 10, 20]), None);
+
+        assert_eq!(selected.map(|g| g.app_id), Some(10));
+    }
+
+    #[test]
+    fn priority_falls_back_to_most_recent() {
+        let games = games();
+        let selected = select_game(&games, &selection(SelectionPolicy::Priority, vec![99]), None);
+
+        assert_eq!(selected.map(|g| g.app_id), Some(30));
+    }
+
+    #[test]
+    fn selection_does_not_depend_on_order() {
+        let mut games = vec![game(10, 100, 0.0), game(20, 100, 0.0)];
+        let first = select_game(&games, &GameSelection::default(), None).map(|g| g.app_id);
+        games.reverse();
+        let second = select_game(&games, &GameSelection::default(), None).map(|g| g.app_id);
+
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn breaks_ties_by_lower_app_id() {
+        let games = vec![game(20, 100, 50.0), game(10, 100, 50.0)];
+
+        for policy in [SelectionPolicy::MostRecent, SelectionPolicy::LongestRunning, SelectionPolicy::HighestCpu] {
+            let selected = select_game(&games, &selection(policy, vec![]), None);
+
+            assert_eq!(selected.map(|g| g.app_id), Some(10), "{policy:?}");
+        }
+    }
+
+    #[test]
+    fn keeps_shown_game_when_cpu_usage_is_close() {
+        let games = vec![game(10, 100, 60.0), game(20, 200, 70.0)];
+        let policy = selection(SelectionPolicy::HighestCpu, vec![]);
+
+        assert_eq!(select_game(&games, &policy, Some(10)).map(|g| g.app_id), Some(10));
+        assert_eq!(select_game(&games, &policy, None).map(|g| g.app_id), Some(20));
+
+        let games = vec![game(10, 100, 10.0), game(20, 200, 70.0)];
+        assert_eq!(select_game(&games, &policy, Some(10)).map(|g| g.app_id), Some(20));
+    }
+}
@@ -0,0 +1,198 @@
+//! Answering the age checks of the Steam Store.
+//!
+//! The store has two kinds of age gates: the birthday gate for games with an age rating, and the newer
+//! mature content gate for games with content descriptors, which only asks to confirm. Both are passed
+//! with the cookies the store sets after answering them, or with a logged-in session.
+
+use crate::config::{AgeGate, AgeGatePolicy};
+use anyhow::{anyhow, Context, Result};
+use scraper::{Html, Selector};
+use url::Url;
+
+pub const STORE_URL: &str = "https://store.steampowered.com/";
+/// The store redirects age gated pages to `/agecheck/app/<appid>/`
+const AGECHECK_PATH_SEGMENT: &str = "agecheck";
+/// The birthday gate, and the mature content gate
+const AGEGATE_SELECTORS: [&str; 3] = ["div.agegate_birthday_selector", "#app_agegate", "div.age_gate"];
+const AGE_COOKIE_MAX_AGE_SECS: u64 = 365 * 24 * 60 * 60;
+const SESSION_COOKIE_NAME: &str = "steamLoginSecure";
+
+const MONTHS: [&str; 12] = [
+    "January", "February", "March", "April", "May", "June", "July", "August", "September", "October", "November",
+    "December",
+];
+
+/// How to answer age gates, from the configuration
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AgeGateAnswer {
+    /// Do not answer age gates
+    Never,
+    /// Answer with a birthday
+    Birthday(Birthday),
+    /// Use the `steamLoginSecure` cookie of a logged-in session
+    Session(String),
+}
+
+impl Default for AgeGateAnswer {
+    fn default() -> Self {
+        AgeGateAnswer::Birthday(Birthday { year: 1990, month: 1, day: 1 })
+    }
+}
+
+impl AgeGateAnswer {
+    pub fn from_config(config: &AgeGate) -> Result<Self> {
+        match config.policy {
+            AgeGatePolicy::Never => Ok(AgeGateAnswer::Never),
+            AgeGatePolicy::Birthday => Birthday::parse(&config.birthday)
+                .map(AgeGateAnswer::Birthday)
+                .with_context(|| "Invalid age_gate.birthday"),
+            AgeGatePolicy::Session => config
+                .session_cookie
+                .clone()
+                .filter(|c| !c.is_empty())
+                .map(AgeGateAnswer::Session)
+                .ok_or_else(|| anyhow!("age_gate.policy is session, but age_gate.session_cookie is not set")),
+        }
+    }
+}
+
+/// A date of birth
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Birthday {
+    pub year: i32,
+    /// 1 to 12
+    pub month: u32,
+    pub day: u32,
+}
+
+impl Birthday {
+    /// Parses a date in the format `YYYY-MM-DD`
+    pub fn parse(date: &str) -> Result<Self> {
+        let parts: Vec<&str> = date.split('-').collect();
+        let [year, month, day] = parts.as_slice() else {
+            return Err(anyhow!("Expected a date like 1990-01-31, found {date:?}"));
+        };
+
+        let birthday = Birthday {
+            year: year.parse().with_context(|| format!("Invalid year in {date:?}"))?,
+            month: month.parse().with_context(|| format!("Invalid month in {date:?}"))?,
+            day: day.parse().with_context(|| format!("Invalid day in {date:?}"))?,
+        };
+
+        match (1..=12).contains(&birthday.month) && (1..=birthday.days_in_month()).contains(&birthday.day) {
+            true => Ok(birthday),
+            false => Err(anyhow!("{date:?} is not a valid date")),
+        }
+    }
+
+    /// The number of days in the month of the birthday
+    fn days_in_month(&self) -> u32 {
+        let leap_year = self.year % 4 == 0 && (self.year % 100 != 0 || self.year % 400 == 0);
+
+        match self.month {
+            2 if leap_year => 29,
+            2 => 28,
+            4 | 6 | 9 | 11 => 30,
+            _ => 31,
+        }
+    }
+
+    /// The English name of the month, which the age gate form expects
+    pub fn month_name(&self) -> &'static str {
+        MONTHS[self.month as usize - 1]
+    }
+
+    /// Unix timestamp (seconds) of midnight UTC on the birthday
+    pub fn unix_time(&self) -> i64 {
+        super::days_from_civil(self.year as i64, self.month as i64, self.day as i64) * 24 * 60 * 60
+    }
+}
+
+/// Checks if a store page is an age gate instead of the actual page
+pub fn is_age_gate(url: &Url, html: &str) -> bool {
+    if url.path_segments().and_then(|mut s| s.next()) == Some(AGECHECK_PATH_SEGMENT) {
+        return true;
+    }
+
+    let document = Html::parse_document(html);
+
+    AGEGATE_SELECTORS
+        .iter()
+        .map(|s| Selector::parse(s).unwrap())
+        .any(|s| document.select(&s).next().is_some())
+}
+
+/// The cookies the store sets after its age gates were answered with the given birthday
+pub fn age_cookies(birthday: &Birthday) -> Vec<String> {
+    let attributes = format!("Path=/; Max-Age={AGE_COOKIE_MAX_AGE_SECS}");
+
+    vec![
+        format!("birthtime={}; {attributes}", birthday.unix_time()),
+        // the store counts months from 0 in this one
+        format!("lastagecheckage={}-{}-{}; {attributes}", birthday.day, birthday.month - 1, birthday.year),
+        format!("wants_mature_content=1; {attributes}"),
+    ]
+}
+
+/// The cookie of a logged-in Steam session
+pub fn session_cookie(value: &str) -> String {
+    format!("{SESSION_COOKIE_NAME}={value}; Path=/; Secure; HttpOnly")
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn parses_birthday() -> Result<()> {
+        let birthday = Birthday::parse("1990-01-01")?;
+
+        assert_eq!(birthday, Birthday { year: 1990, month: 1, day: 1 });
+        assert_eq!(birthday.month_name(), "January");
+        assert_eq!(birthday.unix_time(), 631152000);
+        assert_eq!(Birthday::parse("1969-12-31")?.unix_time(), -86400);
+
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_invalid_birthday() {
+        assert!(Birthday::parse("01.01.1990").is_err());
+        assert!(Birthday::parse("1990-13-01").is_err());
+        assert!(Birthday::parse("1990-01").is_err());
+        assert!(Birthday::parse("1990-04-31").is_err());
+        assert!(Birthday::parse("1990-02-29").is_err());
+        assert!(Birthday::parse("1900-02-29").is_err());
+        assert!(Birthday::parse("1990-01-00").is_err());
+    }
+
+    #[test]
+    fn accepts_leap_days() {
+        assert!(Birthday::parse("1992-02-29").is_ok());
+        assert!(Birthday::parse("2000-02-29").is_ok());
+    }
+
+    #[test]
+    fn detects_age_gates() -> Result<()> {
+        let store_page = Url::parse("https://store.steampowered.com/app/570/")?;
+
+        assert!(is_age_gate(&Url::parse("https://store.steampowered.com/agecheck/app/570/")?, ""));
+        assert!(is_age_gate(&store_page, r#"<div class="agegate_birthday_selector"></div>"#));
+        assert!(is_age_gate(&store_page, r#"<div id="app_agegate"><a id="view_product_page_btn"></a></div>"#));
+        assert!(!is_age_gate(&store_page, r#"<div id="appHubAppName">Dota 2</div>"#));
+
+        Ok(())
+    }
+
+    #[test]
+    fn requires_session_cookie() {
+        let config = AgeGate {
+            policy: AgeGatePolicy::Session,
+            ..Default::default()
+        };
+
+        assert!(AgeGateAnswer::from_config(&config).is_err());
+        assert_eq!(AgeGateAnswer::from_config(&AgeGate::default()).unwrap(), AgeGateAnswer::default());
+    }
+}
@@ -0,0 +1,427 @@
+//! Reader for Steam's binary `appcache/appinfo.vdf`, which has the metadata of every app the client knows about.
+//!
+//! The file has a header, followed by one entry per app with the app's metadata as binary KeyValues.
+//! Since version 29, keys are stored once in a string table at the end of the file.
+
+use super::library::steam_roots;
+use super::vdf::KeyValue;
+use anyhow::{anyhow, bail, Result};
+use once_cell::sync::Lazy;
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufReader, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+use tracing::debug;
+
+const APPINFO_PATH: &str = "appcache/appinfo.vdf";
+const ICON_BASE_URL: &str = "https://cdn.cloudflare.steamstatic.com/steamcommunity/public/images/apps";
+
+const MAGIC_V27: u32 = 0x0756_4427;
+const MAGIC_V28: u32 = 0x0756_4428;
+const MAGIC_V29: u32 = 0x0756_4429;
+/// Version 28 added a hash of the binary data to the entry header
+const BINARY_HASH_LEN: i64 = 20;
+
+// binary KeyValues types
+const TYPE_SECTION: u8 = 0x00;
+const TYPE_STRING: u8 = 0x01;
+const TYPE_INT32: u8 = 0x02;
+const TYPE_FLOAT32: u8 = 0x03;
+const TYPE_POINTER: u8 = 0x04;
+const TYPE_WIDE_STRING: u8 = 0x05;
+const TYPE_COLOR: u8 = 0x06;
+const TYPE_UINT64: u8 = 0x07;
+const TYPE_END: u8 = 0x08;
+const TYPE_INT64: u8 = 0x0A;
+const TYPE_END_ALT: u8 = 0x0B;
+
+/// The metadata of an app from `appinfo.vdf`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AppInfo {
+    pub app_id: u32,
+    pub name: Option<String>,
+    /// Translated names, by Steam language (e.g. `schinese`)
+    pub localized_names: HashMap<String, String>,
+    pub app_type: Option<String>,
+    /// Hash of the app's icon on the Steam CDN
+    pub icon: Option<String>,
+    /// Hash of the app's logo on the Steam CDN
+    pub logo: Option<String>,
+    /// Hash of the icon the Steam client uses for desktop shortcuts
+    pub client_icon: Option<String>,
+    /// The operating systems the app supports, e.g. `windows` or `linux`
+    pub os_list: Vec<String>,
+    pub associations: Vec<Association>,
+}
+
+/// A developer, publisher or franchise of an app
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Association {
+    pub kind: String,
+    pub name: String,
+}
+
+impl AppInfo {
+    fn from_key_values(app_id: u32, kv: &KeyValue) -> Self {
+        let Some(common) = kv.get_path(&["appinfo", "common"]) else {
+            return AppInfo { app_id, ..Default::default() };
+        };
+        let string = |key| common.get_str(&[key]).map(str::to_owned);
+
+        AppInfo {
+            app_id,
+            name: string("name"),
+            localized_names: common
+                .get("name_localized")
+                .map(|n| n.children())
+                .unwrap_or_default()
+                .iter()
+                .filter_map(|(language, name)| match name {
+                    KeyValue::Value(name) => Some((language.to_lowercase(), name.clone())),
+                    KeyValue::Section(_) => None,
+                })
+                .collect(),
+            app_type: string("type"),
+            icon: string("icon"),
+            logo: string("logo"),
+            client_icon: string("clienticon"),
+            os_list: common
+                .get_str(&["oslist"])
+                .map(|l| l.split(',').filter(|os| !os.is_empty()).map(str::to_owned).collect())
+                .unwrap_or_default(),
+            associations: common
+                .get("associations")
+                .map(|a| a.children())
+                .unwrap_or_default()
+                .iter()
+                .filter_map(|(_, a)| {
+                    Some(Association {
+                        kind: a.get_str(&["type"])?.to_owned(),
+                        name: a.get_str(&["name"])?.to_owned(),
+                    })
+                })
+                .collect(),
+        }
+    }
+
+    /// Gets the app's name in a Steam language, if it is translated
+    pub fn localized_name(&self, language: &str) -> Option<&str> {
+        self.localized_names.get(language).map(String::as_str)
+    }
+
+    /// Gets the url of the app's icon on the Steam CDN
+    pub fn icon_url(&self) -> Option<String> {
+        self.icon
+            .as_ref()
+            .map(|hash| format!("{ICON_BASE_URL}/{}/{hash}.jpg", self.app_id))
+    }
+}
+
+/// Where the entries of an `appinfo.vdf` are, so apps can be read without scanning the whole file
+#[derive(Debug)]
+struct AppInfoIndex {
+    binary_hash_len: i64,
+    /// The keys of version 29
+    strings: Option<Vec<String>>,
+    /// The offset of each app's entry, after its size
+    offsets: HashMap<u32, u64>,
+}
+
+/// The indexes of the `appinfo.vdf` files by path, with the modification time of the file they were read from
+type IndexCache = HashMap<PathBuf, (SystemTime, Arc<AppInfoIndex>)>;
+
+static INDEXES: Lazy<Mutex<IndexCache>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Looks up an app in the `appinfo.vdf` of the local Steam installations.
+///
+/// Each file is only scanned once, and again after it changed. This does blocking file system access.
+pub fn find_app_info(app_id: u32) -> Option<AppInfo> {
+    steam_roots().iter().find_map(|root| {
+        let path = root.join(APPINFO_PATH);
+        let file = File::open(&path).ok()?;
+
+        match read_app_info(&path, file, app_id) {
+            Ok(info) => info,
+            Err(e) => {
+                debug!("Error reading {APPINFO_PATH}: {e:?}");
+                None
+            }
+        }
+    })
+}
+
+fn read_app_info(path: &Path, file: File, app_id: u32) -> Result<Option<AppInfo>> {
+    let modified = file.metadata()?.modified()?;
+    let mut reader = BufReader::new(file);
+
+    let index = {
+        let mut indexes = INDEXES.lock().unwrap();
+
+        match indexes.get(path).filter(|(m, _)| *m == modified) {
+            Some((_, index)) => Arc::clone(index),
+            None => {
+                let index = Arc::new(read_index(&mut reader)?);
+                indexes.insert(path.to_owned(), (modified, Arc::clone(&index)));
+                index
+            }
+        }
+    };
+
+    read_entry(&mut reader, &index, app_id)
+}
+
+/// Reads the header and the offsets of all entries
+fn read_index<R: Read + Seek>(reader: &mut R) -> Result<AppInfoIndex> {
+    let magic = read_u32(reader)?;
+    let _universe = read_u32(reader)?;
+
+    let (binary_hash_len, strings) = match magic {
+        MAGIC_V27 => (0, None),
+        MAGIC_V28 => (BINARY_HASH_LEN, None),
+        MAGIC_V29 => {
+            let offset = read_u64(reader)?;
+            (BINARY_HASH_LEN, Some(read_string_table(reader, offset)?))
+        }
+        other => bail!("Unsupported appinfo.vdf version {other:#010x}"),
+    };
+
+    let mut offsets = HashMap::new();
+
+    loop {
+        let app_id = read_u32(reader)?;
+        // the list of entries ends with an AppId of 0
+        if app_id == 0 {
+            return Ok(AppInfoIndex { binary_hash_len, strings, offsets });
+        }
+
+        let size = read_u32(reader)?;
+        offsets.insert(app_id, reader.stream_position()?);
+        reader.seek(SeekFrom::Current(size as i64))?;
+    }
+}
+
+/// Reads the entry of an app, if the file has one
+fn read_entry<R: Read + Seek>(reader: &mut R, index: &AppInfoIndex, app_id: u32) -> Result<Option<AppInfo>> {
+    let Some(&offset) = index.offsets.get(&app_id) else {
+        return Ok(None);
+    };
+
+    // info state, last update, PICS token, text hash and change number
+    reader.seek(SeekFrom::Start(offset + 4 + 4 + 8 + 20 + 4 + index.binary_hash_len as u64))?;
+    let kv = read_section(reader, index.strings.as_deref())?;
+
+    Ok(Some(AppInfo::from_key_values(app_id, &kv)))
+}
+
+/// Reads the string table of version 29 and returns to the current position
+fn read_string_table<R: Read + Seek>(reader: &mut R, offset: u64) -> Result<Vec<String>> {
+    let position = reader.stream_position()?;
+    reader.seek(SeekFrom::Start(offset))?;
+
+    let count = read_u32(reader)?;
+    let strings = (0..count).map(|_| read_string(reader)).collect::<Result<_>>()?;
+
+    reader.seek(SeekFrom::Start(position))?;
+
+    Ok(strings)
+}
+
+/// Reads binary KeyValues up to the end of the current section
+fn read_section<R: Read>(reader: &mut R, strings: Option<&[String]>) -> Result<KeyValue> {
+    let mut children = Vec::new();
+
+    loop {
+        let value_type = read_u8(reader)?;
+        if value_type == TYPE_END || value_type == TYPE_END_ALT {
+            return Ok(KeyValue::Section(children));
+        }
+
+        let key = match strings {
+            Some(strings) => {
+                let index = read_u32(reader)?;
+                strings
+                    .get(index as usize)
+                    .cloned()
+                    .ok_or_else(|| anyhow!("Key {index} is not in the string table"))?
+            }
+            None => read_string(reader)?,
+        };
+
+        let value = match value_type {
+            TYPE_SECTION => read_section(reader, strings)?,
+            TYPE_STRING => KeyValue::Value(read_string(reader)?),
+            TYPE_WIDE_STRING => KeyValue::Value(read_wide_string(reader)?),
+            TYPE_INT32 | TYPE_POINTER | TYPE_COLOR => KeyValue::Value((read_u32(reader)? as i32).to_string()),
+            TYPE_FLOAT32 => KeyValue::Value(f32::from_bits(read_u32(reader)?).to_string()),
+            TYPE_UINT64 => KeyValue::Value(read_u64(reader)?.to_string()),
+            TYPE_INT64 => KeyValue::Value((read_u64(reader)? as i64).to_string()),
+            other => bail!("Unknown value type {other:#04x} for key {key:?}"),
+        };
+
+        children.push((key, value));
+    }
+}
+
+fn read_u8<R: Read>(reader: &mut R) -> Result<u8> {
+    let mut buffer = [0; 1];
+    reader.read_exact(&mut buffer)?;
+    Ok(buffer[0])
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> Result<u32> {
+    let mut buffer = [0; 4];
+    reader.read_exact(&mut buffer)?;
+    Ok(u32::from_le_bytes(buffer))
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> Result<u64> {
+    let mut buffer = [0; 8];
+    reader.read_exact(&mut buffer)?;
+    Ok(u64::from_le_bytes(buffer))
+}
+
+/// Reads a null-terminated UTF-8 string
+fn read_string<R: Read>(reader: &mut R) -> Result<String> {
+    let mut bytes = Vec::new();
+
+    loop {
+        match read_u8(reader)? {
+            0 => return Ok(String::from_utf8_lossy(&bytes).into_owned()),
+            b => bytes.push(b),
+        }
+    }
+}
+
+/// Reads a null-terminated UTF-16 string
+fn read_wide_string<R: Read>(reader: &mut R) -> Result<String> {
+    let mut units = Vec::new();
+
+    loop {
+        let mut buffer = [0; 2];
+        reader.read_exact(&mut buffer)?;
+
+        match u16::from_le_bytes(buffer) {
+            0 => return Ok(String::from_utf16_lossy(&units)),
+            unit => units.push(unit),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::io::Cursor;
+
+    const APPINFO_V28: &[u8] = include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/appinfo_v28.vdf"));
+    const APPINFO_V29: &[u8] = include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/appinfo_v29.vdf"));
+
+    fn read_app_info(data: &[u8], app_id: u32) -> Result<Option<AppInfo>> {
+        let mut reader = Cursor::new(data);
+        let index = read_index(&mut reader)?;
+
+        read_entry(&mut reader, &index, app_id)
+    }
+
+    #[test]
+    fn reads_version_28() -> Result<()> {
+        let info = read_app_info(APPINFO_V28, 570)?.unwrap();
+
+        assert_eq!(info.name.as_deref(), Some("Dota 2"));
+        assert_eq!(info.app_type.as_deref(), Some("Game"));
+        assert_eq!(info.os_list, ["windows", "macos", "linux"]);
+        assert_eq!(
+            info.associations,
+            [
+                Association { kind: "developer".to_owned(), name: "Valve".to_owned() },
+                Association { kind: "publisher".to_owned(), name: "Valve".to_owned() },
+            ]
+        );
+        assert_eq!(
+            info.icon_url().as_deref(),
+            Some("https://cdn.cloudflare.steamstatic.com/steamcommunity/public/images/apps/570/0bbb630d63262dd66d2fdd0f7d37e8661a410075.jpg")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn reads_version_29_string_table() -> Result<()> {
+        let dota = read_app_info(APPINFO_V29, 570)?.unwrap();
+        let beat_saber = read_app_info(APPINFO_V29, 620980)?.unwrap();
+
+        assert_eq!(dota, read_app_info(APPINFO_V28, 570)?.unwrap());
+        assert_eq!(beat_saber.name.as_deref(), Some("Beat Saber"));
+        assert!(beat_saber.icon.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn returns_none_for_unknown_app() -> Result<()> {
+        assert!(read_app_info(APPINFO_V29, 730)?.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn indexes_all_entries() -> Result<()> {
+        let index = read_index(&mut Cursor::new(APPINFO_V29))?;
+
+        let mut app_ids: Vec<u32> = index.offsets.keys().copied().collect();
+        app_ids.sort_unstable();
+
+        assert_eq!(app_ids, [570, 620980]);
+        assert!(index.strings.is_some());
+
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_unknown_version() {
+        let header = [0x26, 0x44, 0x56, 0x07, 0x01, 0x00, 0x00, 0x00];
+
+        assert!(read_app_info(&header, 570).is_err());
+    }
+
+    #[test]
+    fn reads_localized_names() -> Result<()> {
+        let kv = super::super::vdf::parse(
+            r#""appinfo" { "common" { "name" "Dota 2" "name_localized" { "schinese" "刀塔2" "koreana" "도타 2" } } }"#,
+        )?;
+
+        let info = AppInfo::from_key_values(570, &kv);
+
+        assert_eq!(info.localized_name("schinese"), Some("刀塔2"));
+        assert_eq!(info.localized_name("german"), None);
+        assert_eq!(info.name.as_deref(), Some("Dota 2"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn reads_numbers_and_wide_strings() -> Result<()> {
+        let mut data = vec![TYPE_INT32];
+        data.extend(b"count\0");
+        data.extend((-2i32).to_le_bytes());
+        data.push(TYPE_UINT64);
+        data.extend(b"token\0");
+        data.extend(u64::MAX.to_le_bytes());
+        data.push(TYPE_WIDE_STRING);
+        data.extend(b"title\0");
+        data.extend("Äb".encode_utf16().chain([0]).flat_map(u16::to_le_bytes));
+        data.push(TYPE_END);
+
+        let kv = read_section(&mut Cursor::new(data), None)?;
+
+        assert_eq!(kv.get_str(&["count"]), Some("-2"));
+        assert_eq!(kv.get_u64(&["token"]), Some(u64::MAX));
+        assert_eq!(kv.get_str(&["title"]), Some("Äb"));
+
+        Ok(())
+    }
+}
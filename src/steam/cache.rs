@@ -1,179 +1,425 @@
+use super::agegate::{self, AgeGateAnswer};
+use super::cookies;
+use super::http::HttpClient;
+use super::inflight::InFlight;
+use super::index::{Extracted, Failure, FetchMetadata, IndexLimits, MetadataIndex, Record, Source};
+use super::steamgriddb::{self, AssetKind};
+use super::store::{self, StoreLocale};
+use crate::config;
 use anyhow::{anyhow, Context, Result};
 use html_escape::decode_html_entities;
-use reqwest_cookie_store::{CookieStore, CookieStoreMutex};
+use reqwest::{
+    header::{AUTHORIZATION, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED},
+    Response, StatusCode,
+};
+use reqwest_cookie_store::CookieStoreMutex;
 use scraper::{ElementRef, Html, Selector};
 use std::{
-    collections::HashMap,
-    fs::File,
-    io::{BufReader, BufWriter},
-    path::PathBuf,
-    sync::Arc,
+    collections::{HashMap, HashSet},
+    ffi::OsString,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
-use tokio::fs::{read_to_string, write};
 use tracing::{event, Level};
 use url::Url;
 
-// XDG RUNTIME HOME
+// XDG CACHE HOME
 
+const XDG_CACHE_ENV_VAR: &str = "XDG_CACHE_HOME";
+const HOME_ENV_VAR: &str = "HOME";
+const HOME_CACHE_DIR: &str = ".cache";
+/// Older versions kept the cache in the runtime directory, which is cleared on every reboot
 const XDG_RUNTIME_ENV_VAR: &str = "XDG_RUNTIME_DIR";
-const CACHE_DIR: &str = "cache";
-const COOKIE_STORE_PATH_OLD: &str = "cookies.json";
-const COOKIE_STORE_PATH: &str = "cookies";
+/// Older versions kept the cache in this subdirectory of the app's directory
+const LEGACY_CACHE_DIR: &str = "cache";
+/// Older versions stored the metadata of each document in a separate file with this extension
+const LEGACY_METADATA_EXTENSION: &str = "meta";
+const DEFAULT_TTL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+/// How long a failed download keeps us from asking the store again
+const FAILURE_TTL: Duration = Duration::from_secs(15 * 60);
+const DEFAULT_MAX_ENTRIES: usize = 5000;
+const DEFAULT_MAX_SIZE_BYTES: u64 = 4 * 1024 * 1024;
+/// How often changes to the metadata index are written to disk at most
+const INDEX_SAVE_INTERVAL: Duration = Duration::from_secs(60);
+/// The index key of documents downloaded without a locale, see [StoreLocale::query]
+const DEFAULT_LOCALE: &str = "";
+/// Left behind by writes that were interrupted
+const TEMP_EXTENSION: &str = "tmp";
 
 const STEAM_NAME_SELECTOR: &str = "#appHubAppName";
 const STEAM_ICON_SELECTOR: &str = "div.apphub_AppIcon img";
 
-const AGEGATE_SELECTOR: &str = "div.agegate_birthday_selector";
 const AGESET_BASE_URL: &str = "https://store.steampowered.com/agecheckset/app/";
 
 const SESSION_ID_COOKIE_NAME: &str = "sessionid";
 const SESSION_ID_COOKIE_DOMAIN: &str = "store.steampowered.com";
 
+impl Extracted {
+    /// Fills the fields that are missing from another document
+    fn or(self, other: Extracted) -> Extracted {
+        Extracted {
+            name: self.name.or(other.name),
+            app_type: self.app_type.or(other.app_type),
+            icon_url: self.icon_url.or(other.icon_url),
+            header_image: self.header_image.or(other.header_image),
+        }
+    }
+}
+
+impl FetchMetadata {
+    fn from_response(response: &Response) -> Self {
+        let header = |name| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_owned)
+        };
+
+        Self {
+            fetched_at: unix_now(),
+            etag: header(ETAG),
+            last_modified: header(LAST_MODIFIED),
+            status: response.status().as_u16(),
+        }
+    }
+}
+
+/// The result of downloading a document that might already be indexed
+enum Fetched {
+    /// The indexed record is still up to date
+    NotModified,
+    Document(String, FetchMetadata),
+    /// The store redirected away from the app's page to this url, it does not show the app
+    NotOnStore(Url),
+    /// The store page is behind an age gate we do not answer
+    AgeGated(FetchMetadata),
+}
+
+/// What the index knows about a record that is looked up
+#[derive(Debug, PartialEq)]
+enum Lookup {
+    Fresh(Record),
+    /// The record is older than the TTL and should be revalidated
+    Stale(Record),
+    /// The record is not indexed, and the last download failed less than [FAILURE_TTL] ago
+    RecentFailure(Failure),
+    /// The record is not indexed, or only with a failure long ago
+    Missing,
+}
+
 #[derive(Debug)]
 pub struct DocumentCache {
     /// The location of the document cache in the file system
     location: String,
     cookies: Arc<CookieStoreMutex>,
+    /// The client for all downloads, which uses [DocumentCache::cookies]
+    http: HttpClient,
+    /// How long an indexed record is used before it is revalidated
+    ttl: Duration,
+    /// The metadata extracted from downloaded documents
+    index: MetadataIndex,
+    /// The language and country documents are downloaded in
+    locale: StoreLocale,
+    /// How to answer the store's age gates
+    age_gate: AgeGateAnswer,
+    /// The key for downloads from SteamGridDB
+    steamgriddb_api_key: Option<String>,
+    /// The cookies as they were last written to disk, to only write them when they changed
+    saved_cookies: Mutex<Option<String>>,
+    /// Records that are being refreshed in the background
+    refreshing: Mutex<HashSet<(u32, Source, String)>>,
+    /// Downloads of records that are not indexed yet, shared by concurrent lookups of the same app
+    downloading: InFlight<(u32, Source, String), Result<Record, String>>,
 }
 
 impl DocumentCache {
     /// Creates a new [DocumentCache](#DocumentCache) with the given location.
-    pub fn new(cache_loc: String) -> Self {
+    pub fn new(
+        cache_loc: String,
+        ttl: Duration,
+        limits: IndexLimits,
+        locale: StoreLocale,
+        age_gate: AgeGateAnswer,
+        steamgriddb_api_key: Option<String>,
+        http: &config::Http,
+    ) -> Result<Self> {
         let mut location = PathBuf::new();
         location.push(&cache_loc);
 
-        let mut new_store_path = location.clone();
-        new_store_path.push(COOKIE_STORE_PATH);
-        let mut old_store_path = location.clone();
-        old_store_path.push(COOKIE_STORE_PATH_OLD);
+        let mut cookie_store = cookies::load(&location);
+        cookies::prune(&mut cookie_store);
 
-        let cookie_store = if new_store_path.exists() {
-            if let Ok(store_reader) = File::open(new_store_path).map(BufReader::new) {
-                if old_store_path.exists() {
-                    std::fs::remove_file(&old_store_path).unwrap();
-                }
-                CookieStore::load(store_reader, |c| serde_json::from_str(c)).unwrap()
-            } else {
-                CookieStore::new(None)
-            }
-        } else if old_store_path.exists() {
-            if let Ok(store_reader) = File::open(old_store_path).map(BufReader::new) {
-                let store = CookieStore::load_json(store_reader).unwrap(); // deprecated, but this is for migration purposes only. Will be removed at a later date
-                store
-            } else {
-                CookieStore::new(None)
+        if let AgeGateAnswer::Session(session) = &age_gate {
+            if let Err(e) = cookie_store.parse(&agegate::session_cookie(session), &Url::parse(agegate::STORE_URL).unwrap()) {
+                event!(Level::WARN, "Error using the configured Steam session: {e}");
             }
-        } else {
-            CookieStore::new(None)
-        };
+        }
 
+        let saved_cookies = cookies::serialize(&cookie_store).ok();
         let cookie_store = Arc::new(CookieStoreMutex::new(cookie_store));
+        let http = HttpClient::new(http, Arc::clone(&cookie_store))?;
 
-        Self {
+        let index = MetadataIndex::load(&location, limits);
+        migrate_documents(&location, &index);
+        remove_orphans(&location);
+
+        Ok(Self {
             location: cache_loc,
             cookies: cookie_store,
-        }
+            http,
+            ttl,
+            index,
+            locale,
+            age_gate,
+            steamgriddb_api_key,
+            saved_cookies: Mutex::new(saved_cookies),
+            refreshing: Mutex::new(HashSet::new()),
+            downloading: InFlight::new(),
+        })
+    }
+
+    /// Checks if the index has a record of an app in the configured locale that is younger than the TTL, from any source
+    pub fn is_fresh(&self, app_id: u32) -> bool {
+        let locale = self.locale.query();
+
+        [Source::StoreApi, Source::StorePage]
+            .into_iter()
+            .filter_map(|source| self.index.get(app_id, &locale, source))
+            .any(|record| unix_now() - record.fetch.fetched_at < self.ttl.as_secs() as i64)
     }
 
-    /// Get a game's name.
+    /// The language and country documents are downloaded in
+    pub fn locale(&self) -> &StoreLocale {
+        &self.locale
+    }
+
+    /// Gets the fields extracted from one of an app's store documents, in the configured locale.
     ///
-    /// Returns `anyhow::Result<String>`
+    /// All fields are `None` if the app is not on the store (delisted, blocked in our region or never published).
+    /// If the document in the configured language has no name, the missing fields are taken from the English one.
     ///
     /// Parameters:
-    /// * `steam_url: &str`: the url of the game's steam store page
-    pub async fn get_name(&self, steam_url: &str) -> Result<String> {
-        let name_selector = Selector::parse(STEAM_NAME_SELECTOR).unwrap();
-        let html = match self.get_steam_page(steam_url).await {
-            Ok(h) => get_html(&h),
-            Err(err) => return Err(anyhow!(err)),
+    /// * `source: Source`: the document to extract the fields from
+    /// * `app_id: u32`: the app's AppId
+    pub async fn get_fields(&'static self, source: Source, app_id: u32) -> Result<Extracted> {
+        let localized = self.get_fields_in(source, app_id, &self.locale).await;
+
+        if !self.locale.is_translated() || localized.as_ref().is_ok_and(|f| f.name.is_some()) {
+            return localized;
+        }
+
+        event!(Level::DEBUG, %app_id, ?source, "No localized name, falling back to English");
+
+        match (localized, self.get_fields_in(source, app_id, &self.locale.english()).await) {
+            (Ok(localized), Ok(english)) => Ok(localized.or(english)),
+            (Ok(fields), Err(_)) | (Err(_), Ok(fields)) => Ok(fields),
+            (Err(e), Err(_)) => Err(e),
+        }
+    }
+
+    async fn get_fields_in(&'static self, source: Source, app_id: u32, locale: &StoreLocale) -> Result<Extracted> {
+        let url = document_url(source, app_id, locale);
+
+        Ok(self.get_record(source, app_id, &locale.query(), &url).await?.fields)
+    }
+
+    /// Gets the url of an app's first asset of a kind on SteamGridDB, `None` if it has none.
+    ///
+    /// The answers are indexed like store documents, with the same TTL, and failures are remembered for [FAILURE_TTL].
+    pub async fn get_artwork(&'static self, kind: AssetKind, app_id: u32) -> Result<Option<String>> {
+        let source = match kind {
+            AssetKind::Icons => Source::SteamgriddbIcons,
+            AssetKind::Grids => Source::SteamgriddbGrids,
         };
+        // artwork is the same in every language
+        let url = steamgriddb::get_assets_url(kind, app_id);
+        let fields = self.get_record(source, app_id, DEFAULT_LOCALE, &url).await?.fields;
+
+        Ok(fields.icon_url.or(fields.header_image))
+    }
 
-        let found_elements: Vec<ElementRef> = html.select(&name_selector).collect();
-        match found_elements.len() {
-            0 => Err(anyhow!("Could not find any name elements on page")),
-            1 => Ok(decode_html_entities(found_elements[0].inner_html().as_str()).to_string()),
-            _ => Err(anyhow!("Found more than one name element on the page")),
+    /// Gets an app's record from the index, or downloads the document and indexes it.
+    ///
+    /// Records older than the TTL are still returned, but revalidated in the background.
+    /// After a failed download, the store is not asked again for [FAILURE_TTL].
+    async fn get_record(&'static self, source: Source, app_id: u32, locale: &str, url: &str) -> Result<Record> {
+        self.index.touch(app_id, locale);
+
+        match self.lookup(source, app_id, locale, unix_now()) {
+            Lookup::Fresh(record) => Ok(record),
+            Lookup::Stale(record) => {
+                self.refresh_in_background(source, app_id, locale.to_owned(), url.to_owned(), record.clone());
+                Ok(record)
+            }
+            Lookup::RecentFailure(failure) => {
+                Err(anyhow!("Not asking the store about app {app_id} again yet, the last attempt failed: {}", failure.reason))
+            }
+            Lookup::Missing => self.download_record(source, app_id, locale, url).await,
         }
     }
 
-    /// Get a game's app icon
+    /// Looks up a record in the index, and decides if it is still good to use at `now`.
     ///
-    /// Returns `anyhow::Result<String>` as url
+    /// Stale records are not revalidated while the last attempt to do that failed less than [FAILURE_TTL] ago.
+    fn lookup(&self, source: Source, app_id: u32, locale: &str, now: i64) -> Lookup {
+        let recent_failure = self
+            .index
+            .failure(app_id, locale, source)
+            .filter(|f| now - f.failed_at < FAILURE_TTL.as_secs() as i64);
+
+        match (self.index.get(app_id, locale, source), recent_failure) {
+            (Some(record), None) if now - record.fetch.fetched_at >= self.ttl.as_secs() as i64 => Lookup::Stale(record),
+            (Some(record), _) => Lookup::Fresh(record),
+            (None, Some(failure)) => Lookup::RecentFailure(failure),
+            (None, None) => Lookup::Missing,
+        }
+    }
+
+    /// Downloads a record that is not indexed yet. Concurrent lookups of the same record wait for the same download.
+    async fn download_record(&self, source: Source, app_id: u32, locale: &str, url: &str) -> Result<Record> {
+        self.downloading
+            .run((app_id, source, locale.to_owned()), || async {
+                self.update_record(source, app_id, locale, url, None)
+                    .await
+                    .map_err(|e| format!("{e:#}"))
+            })
+            .await
+            .map_err(|e| anyhow!(e))
+    }
+
+    /// Revalidates a stale record without making the caller wait for it
+    fn refresh_in_background(&'static self, source: Source, app_id: u32, locale: String, url: String, record: Record) {
+        tokio::spawn(async move {
+            self.revalidate(source, app_id, &locale, &url, record).await;
+        });
+    }
+
+    /// Revalidates an app's stale store documents in the configured locale, and waits until that is done.
     ///
-    /// Parameters:
-    /// * `steam_url: &str`: the url of the game's steam store page
-    pub async fn get_appicon(&self, steam_url: &str) -> Result<String> {
-        let img_selector = Selector::parse(STEAM_ICON_SELECTOR).unwrap();
-        let html = self.get_steam_page(steam_url).await.map(|h| get_html(&h))?;
+    /// [DocumentCache::get_fields] only starts revalidating stale records, which never finishes when the process exits
+    /// right after, like with `--prefetch`.
+    pub async fn refresh(&self, app_id: u32) {
+        let locale = self.locale.query();
+
+        for source in [Source::StoreApi, Source::StorePage] {
+            let url = document_url(source, app_id, &self.locale);
+            self.refresh_record(source, app_id, &locale, &url, unix_now()).await;
+        }
+    }
 
-        let found_elements: Vec<ElementRef> = html.select(&img_selector).collect();
-        match found_elements.len() {
-            0 => Err(anyhow!("Could not find the icon image on the page")),
-            1 => Ok(found_elements[0].value().attr("src").unwrap().to_string()),
-            _ => Err(anyhow!("Found more than one app icon on the page")),
+    /// Revalidates a record if it is stale at `now`
+    async fn refresh_record(&self, source: Source, app_id: u32, locale: &str, url: &str, now: i64) {
+        if let Lookup::Stale(record) = self.lookup(source, app_id, locale, now) {
+            self.revalidate(source, app_id, locale, url, record).await;
         }
     }
 
-    /// Downloads the given url, if available
-    async fn get_steam_page(&self, url: &str) -> Result<String> {
-        // Check if cache exists
-        let cache_path = self.get_cache_path(url)?;
+    /// Downloads a stale record again, unless that is already being done
+    async fn revalidate(&self, source: Source, app_id: u32, locale: &str, url: &str, record: Record) {
+        let key = (app_id, source, locale.to_owned());
 
-        match cache_path.try_exists()? {
-            true => Ok(read_to_string(&cache_path).await?),
-            false => {
-                // get supposed cache path
-                let document = self.download_steam_page(url).await?;
+        if !self.refreshing.lock().unwrap().insert(key.clone()) {
+            return;
+        }
 
-                write(&cache_path, &document).await?;
+        event!(Level::DEBUG, %url, "Revalidating stale cache entry");
 
-                Ok(document)
-            }
+        if let Err(e) = self.update_record(source, app_id, locale, url, Some(record)).await {
+            event!(Level::DEBUG, %url, "Error revalidating cache entry: {e:?}");
         }
+
+        self.refreshing.lock().unwrap().remove(&key);
     }
 
-    async fn download_steam_page(&self, url: &str) -> Result<String> {
-        let rest_client = self
-            .build_client()
-            .with_context(|| "Error building rest client for cache")?;
+    /// Downloads a document and indexes the fields extracted from it.
+    ///
+    /// Failures are indexed as well, the record from an earlier download is kept in that case.
+    async fn update_record(&self, source: Source, app_id: u32, locale: &str, url: &str, cached: Option<Record>) -> Result<Record> {
+        let fetched = self.fetch(source, app_id, url, cached.as_ref().map(|r| &r.fetch)).await;
+        // the store sets session and age gate cookies with its responses
+        self.save_cookies();
 
-        let request = rest_client.get(url).build()?;
-        let response = rest_client.execute(request).await?;
+        let result = to_record(source, app_id, fetched, cached);
 
-        let (resp_content, is_age_gate) = {
-            let resp_html = get_html(response.text().await?.as_str());
+        match &result {
+            Ok(record) => self.index.insert(app_id, locale, source, record.clone()),
+            Err(e) => self.index.insert_failure(app_id, locale, source, Failure { failed_at: unix_now(), reason: format!("{e:#}") }),
+        }
+        self.flush_index().await;
 
-            let is_age_gate = {
-                let gate_selector = Selector::parse(AGEGATE_SELECTOR).unwrap();
-                let mut age_gate_div = resp_html.select(&gate_selector);
+        result
+    }
 
-                age_gate_div.next().is_some()
-            };
+    /// Writes the changes to the metadata index to disk, unless it was written less than [INDEX_SAVE_INTERVAL] ago.
+    ///
+    /// Changes that are held back are written by a later call, or by [DocumentCache::save_index_blocking] at shutdown.
+    pub async fn flush_index(&self) {
+        if let Err(e) = self.index.flush(INDEX_SAVE_INTERVAL).await {
+            event!(Level::WARN, "Error writing the metadata index: {e:?}");
+        }
+    }
 
-            (resp_html.html(), is_age_gate)
-        };
+    /// Writes the metadata index to disk, for use outside of the async runtime, e.g. at shutdown.
+    ///
+    /// This keeps the times apps were last looked up, which decide what is evicted first.
+    pub fn save_index_blocking(&self) {
+        if let Err(e) = self.index.save_blocking() {
+            event!(Level::WARN, "Error writing the metadata index: {e:?}");
+        }
+    }
+
+    /// Downloads a document, conditionally if we have the metadata of an indexed version.
+    ///
+    /// Error pages and redirects away from the app's store page are returned as errors.
+    async fn fetch(&self, source: Source, app_id: u32, url: &str, cached: Option<&FetchMetadata>) -> Result<Fetched> {
+        let mut request = self.http.get(url);
+        if let Some(etag) = cached.and_then(|m| m.etag.as_ref()) {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = cached.and_then(|m| m.last_modified.as_ref()) {
+            request = request.header(IF_MODIFIED_SINCE, last_modified);
+        }
+        if matches!(source, Source::SteamgriddbIcons | Source::SteamgriddbGrids) {
+            let api_key = self.steamgriddb_api_key.as_ref().with_context(|| "No SteamGridDB API key configured")?;
+            request = request.header(AUTHORIZATION, format!("Bearer {api_key}"));
+        }
+
+        let response = self.http.send(request).await?;
+
+        if response.status() == StatusCode::NOT_MODIFIED {
+            return Ok(Fetched::NotModified);
+        }
 
-        if is_age_gate {
-            let app_id = Self::get_appid_from_url(url)?;
-            if self.handle_agegate(app_id, &rest_client).await.is_ok() {
-                event!(Level::DEBUG, %app_id, "Redoing original request after handling age gate");
-                let request = rest_client.get(url).build()?;
-                let response = rest_client.execute(request).await?;
+        let (mut document, mut metadata, mut final_url) = read_response(response).await?;
 
-                Ok(response.text().await?)
-            } else {
-                Err(anyhow!("Error handling the age gate"))
+        if source == Source::StorePage && agegate::is_age_gate(&final_url, &document) {
+            // not answering is a deliberate choice, so it is indexed like any other page instead of being retried
+            if matches!(self.age_gate, AgeGateAnswer::Never) {
+                event!(Level::DEBUG, %app_id, "Not answering the age gate, age_gate.policy is never");
+                return Ok(Fetched::AgeGated(metadata));
+            }
+
+            self.handle_agegate(app_id)
+                .await
+                .with_context(|| "Error handling the age gate")?;
+
+            event!(Level::DEBUG, %app_id, "Redoing original request after handling age gate");
+            (document, metadata, final_url) = read_response(self.http.send(self.http.get(url)).await?).await?;
+
+            if agegate::is_age_gate(&final_url, &document) {
+                return Err(anyhow!("The store still shows the age gate after answering it"));
             }
-        } else {
-            Ok(resp_content)
         }
-    }
 
-    fn build_client(&self) -> Result<reqwest::Client> {
-        reqwest::ClientBuilder::new()
-            .cookie_provider(Arc::clone(&self.cookies))
-            .build()
-            .with_context(|| "Error building reqwest client")
+        // the store redirects to its front page for apps it does not show
+        if source == Source::StorePage && !is_store_page_of(&final_url, app_id) {
+            event!(Level::DEBUG, %app_id, %final_url, "The store redirected away from the app's page");
+            return Ok(Fetched::NotOnStore(final_url));
+        }
+
+        Ok(Fetched::Document(document, metadata))
     }
 
     fn get_session_cookie_value(&self) -> Result<String> {
@@ -191,24 +437,53 @@ impl DocumentCache {
         }
     }
 
-    async fn handle_agegate(&self, app_id: i64, client: &reqwest::Client) -> Result<()> {
+    /// Answers an age gate according to the configured policy
+    async fn handle_agegate(&self, app_id: u32) -> Result<()> {
         event!(Level::DEBUG, %app_id, "Handling age gate for {}", &app_id);
-        let session_id = self.get_session_cookie_value()?;
 
-        // time to lie about our age (or not, in some freak occurrences)
-        let mut ageset_form = HashMap::new();
-        ageset_form.insert("sessionid", session_id.as_str());
-        ageset_form.insert("ageDay", "1");
-        ageset_form.insert("ageMonth", "January");
-        ageset_form.insert("ageYear", "1990");
+        let birthday = match &self.age_gate {
+            AgeGateAnswer::Birthday(birthday) => birthday,
+            AgeGateAnswer::Never => return Err(anyhow!("Not answering the age gate, age_gate.policy is never")),
+            AgeGateAnswer::Session(_) => {
+                return Err(anyhow!("The configured Steam session cannot see this page, check the account's content preferences"))
+            }
+        };
+
+        // these cookies pass the mature content gate, and the birthday gate on newer store pages
+        {
+            let store_url = Url::parse(agegate::STORE_URL)?;
+            let mut cookies = self.cookies.lock().unwrap();
 
-        let ageset_post = client
-            .post(format!("{}{}", AGESET_BASE_URL, app_id))
-            .form(&ageset_form)
-            .build()?;
+            for cookie in agegate::age_cookies(birthday) {
+                cookies.parse(&cookie, &store_url)?;
+            }
+        }
 
-        let ageset_resp = client
-            .execute(ageset_post)
+        // older store pages want the birthday posted as well
+        let session_id = match self.get_session_cookie_value() {
+            Ok(session_id) => session_id,
+            Err(e) => {
+                event!(Level::DEBUG, %app_id, "Only using age cookies: {e}");
+                return Ok(());
+            }
+        };
+
+        let year = birthday.year.to_string();
+        let day = birthday.day.to_string();
+        let mut ageset_form = HashMap::new();
+        ageset_form.insert("sessionid", session_id.as_str());
+        ageset_form.insert("ageDay", day.as_str());
+        ageset_form.insert("ageMonth", birthday.month_name());
+        ageset_form.insert("ageYear", year.as_str());
+
+        let ageset_post = self
+            .http
+            .post(&format!("{}{}", AGESET_BASE_URL, app_id))
+            .form(&ageset_form);
+
+        let ageset_resp = self
+            .http
+            .send(ageset_post)
             .await
             .with_context(|| "Error submitting information to age gate")?;
 
@@ -224,25 +499,6 @@ impl DocumentCache {
         Ok(())
     }
 
-    fn get_cache_path(&self, url: &str) -> Result<PathBuf> {
-        let steamid_url_part = Self::get_appid_from_url(url)?;
-        let mut path_buff = self.get_location_pathbuf();
-
-        path_buff.push(format!("{steamid_url_part}.html"));
-
-        Ok(path_buff)
-    }
-
-    fn get_appid_from_url(url: &str) -> Result<i64> {
-        let parsed_url = Url::parse(url)?;
-
-        parsed_url
-            .path_segments()
-            .with_context(|| format!("Could not find path in url {url}"))?
-            .find_map(|p| p.parse::<i64>().ok())
-            .with_context(|| format!("Could not find steam id in url {url}"))
-    }
-
     /// Create `PathBuf` from `DocumentCache.location`
     fn get_location_pathbuf(&self) -> PathBuf {
         let mut path_buff = PathBuf::new();
@@ -252,25 +508,33 @@ impl DocumentCache {
         path_buff
     }
 
-    /// Save cookies to temp location.
+    /// Writes the cookies to disk if they changed since they were last written.
     ///
-    /// This allows to reuse age gates after app restarts.
-    fn save_cookies(&self) {
-        let mut writer = {
-            let mut cache_path = self.get_location_pathbuf();
-            cache_path.push(COOKIE_STORE_PATH);
-
-            if cache_path.is_file() {
-                File::open(cache_path).map(BufWriter::new).unwrap()
-            } else {
-                File::create(cache_path).map(BufWriter::new).unwrap()
+    /// This allows to reuse age gates after app restarts. Errors are logged, losing the cookies only means answering age gates again.
+    pub fn save_cookies(&self) {
+        let contents = {
+            let mut store = self.cookies.lock().unwrap();
+            cookies::prune(&mut store);
+            cookies::serialize(&store)
+        };
+
+        let contents = match contents {
+            Ok(c) => c,
+            Err(e) => {
+                event!(Level::WARN, "Error serializing cookies: {e:?}");
+                return;
             }
         };
 
-        let store = self.cookies.lock().unwrap();
-        store
-            .save(&mut writer, |c| serde_json::to_string(c))
-            .unwrap()
+        let mut saved = self.saved_cookies.lock().unwrap();
+        if saved.as_ref() == Some(&contents) {
+            return;
+        }
+
+        match cookies::write(&self.get_location_pathbuf(), &contents) {
+            Ok(()) => *saved = Some(contents),
+            Err(e) => event!(Level::WARN, "Error saving cookies: {e:?}"),
+        }
     }
 }
 
@@ -280,76 +544,427 @@ impl Drop for DocumentCache {
     }
 }
 
+/// Reads a response, failing for error pages so they never end up in the index
+async fn read_response(response: Response) -> Result<(String, FetchMetadata, Url)> {
+    let status = response.status();
+
+    if !status.is_success() {
+        return Err(anyhow!("The store returned {status} for {}", response.url()));
+    }
+
+    let metadata = FetchMetadata::from_response(&response);
+    let final_url = response.url().clone();
+
+    Ok((response.text().await?, metadata, final_url))
+}
+
+/// Checks if a url points to the store page of the given app
+fn is_store_page_of(url: &Url, app_id: u32) -> bool {
+    let mut segments = url.path_segments().into_iter().flatten();
+
+    segments.next() == Some("app") && segments.next() == Some(app_id.to_string().as_str())
+}
+
 /// Parsing a string into an HTML document
 fn get_html(html: &str) -> Html {
     Html::parse_document(html)
 }
 
+/// Turns the result of a download into the record to index.
+///
+/// Apps the store does not show fail like any other download, so they are only remembered for [FAILURE_TTL]
+/// and asked about again after that, in case they are back on the store.
+fn to_record(source: Source, app_id: u32, fetched: Result<Fetched>, cached: Option<Record>) -> Result<Record> {
+    match fetched? {
+        Fetched::NotModified => cached
+            .map(|r| Record { fetch: FetchMetadata { fetched_at: unix_now(), ..r.fetch }, ..r })
+            .ok_or_else(|| anyhow!("Got 304 Not Modified for a document we did not index")),
+        Fetched::Document(document, fetch) => extract(source, app_id, &document).map(|fields| Record { fields, fetch }),
+        Fetched::NotOnStore(final_url) => Err(anyhow!("The store does not show app {app_id}, it redirected to {final_url}")),
+        // the other providers are asked instead
+        Fetched::AgeGated(fetch) => Ok(Record { fields: Extracted::default(), fetch }),
+    }
+}
+
+/// Extracts the fields we use from a downloaded document.
+///
+/// Fails for documents we do not understand and apps the store does not know, those are not indexed.
+fn extract(source: Source, app_id: u32, document: &str) -> Result<Extracted> {
+    match source {
+        Source::StoreApi => store::parse_app_details(app_id, document)?
+            .map(|details| Extracted {
+                name: Some(details.name),
+                app_type: Some(details.app_type),
+                header_image: details.header_image,
+                ..Default::default()
+            })
+            .ok_or_else(|| anyhow!("The store does not know app {app_id}")),
+        Source::StorePage => {
+            let fields = extract_store_page(document);
+
+            // without a name, this is not the app's store page
+            match fields.name {
+                Some(_) => Ok(fields),
+                None => Err(anyhow!("Could not find any name elements on page")),
+            }
+        }
+        Source::SteamgriddbIcons => Ok(Extracted { icon_url: steamgriddb::parse_assets(document)?, ..Default::default() }),
+        Source::SteamgriddbGrids => Ok(Extracted { header_image: steamgriddb::parse_assets(document)?, ..Default::default() }),
+    }
+}
+
+/// Gets the url of an app's document from a source
+fn document_url(source: Source, app_id: u32, locale: &StoreLocale) -> String {
+    match source {
+        Source::StoreApi => store::get_appdetails_url(app_id, locale),
+        Source::StorePage => locale.localize(&super::get_steam_url(app_id)),
+        Source::SteamgriddbIcons => steamgriddb::get_assets_url(AssetKind::Icons, app_id),
+        Source::SteamgriddbGrids => steamgriddb::get_assets_url(AssetKind::Grids, app_id),
+    }
+}
+
+fn extract_store_page(document: &str) -> Extracted {
+    let html = get_html(document);
+    let name_selector = Selector::parse(STEAM_NAME_SELECTOR).unwrap();
+    let icon_selector = Selector::parse(STEAM_ICON_SELECTOR).unwrap();
+
+    let names: Vec<ElementRef> = html.select(&name_selector).collect();
+    let icons: Vec<ElementRef> = html.select(&icon_selector).collect();
+
+    Extracted {
+        // more than one match means the page layout changed, so we cannot trust either of them
+        name: match names.as_slice() {
+            [name] => Some(decode_html_entities(name.inner_html().as_str()).to_string()),
+            _ => None,
+        },
+        icon_url: match icons.as_slice() {
+            [icon] => icon.value().attr("src").map(str::to_owned),
+            _ => None,
+        },
+        ..Default::default()
+    }
+}
+
+/// Moves documents cached by older versions into the index.
+///
+/// Those were stored as `<appid>.html` (store page) or `<appid>.json` (store API), with their metadata in `<file>.meta`.
+/// Older versions did not choose a locale, so they are indexed under the store's default.
+fn migrate_documents(location: &Path, index: &MetadataIndex) {
+    let Ok(entries) = std::fs::read_dir(location) else {
+        return;
+    };
+
+    let documents: Vec<(PathBuf, u32, Source)> = entries
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter_map(|p| legacy_document(&p).map(|(app_id, source)| (p, app_id, source)))
+        .collect();
+
+    if documents.is_empty() {
+        return;
+    }
+
+    event!(Level::INFO, "Moving {} cached documents into the metadata index", documents.len());
+
+    for (path, app_id, source) in &documents {
+        if index.contains(*app_id, DEFAULT_LOCALE, *source) {
+            continue;
+        }
+
+        let fields = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!(e))
+            .and_then(|document| extract(*source, *app_id, &document));
+
+        match fields {
+            Ok(fields) => index.insert(*app_id, DEFAULT_LOCALE, *source, Record { fields, fetch: read_legacy_metadata(path) }),
+            Err(e) => event!(Level::DEBUG, "Dropping cached document {}: {e:?}", path.to_string_lossy()),
+        }
+    }
+
+    // keep the old files if the index could not be written, so we can try again next time
+    if let Err(e) = index.save_blocking() {
+        event!(Level::WARN, "Error writing the metadata index: {e:?}");
+        return;
+    }
+
+    for (path, _, _) in documents {
+        for file in [legacy_metadata_path(&path), path] {
+            if file.exists() {
+                if let Err(e) = std::fs::remove_file(&file) {
+                    event!(Level::WARN, "Error removing {}: {e}", file.to_string_lossy());
+                }
+            }
+        }
+    }
+}
+
+/// Removes files that are no longer used: leftovers of interrupted writes, and metadata of documents older versions
+/// cached, whose document is gone. The legacy cookie jar is removed by [cookies::load].
+fn remove_orphans(location: &Path) {
+    let Ok(entries) = std::fs::read_dir(location) else {
+        return;
+    };
+
+    let orphans: Vec<PathBuf> = entries
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.is_file() && is_orphan(p))
+        .collect();
+
+    let mut freed_bytes = 0;
+    let mut removed = 0;
+
+    for orphan in orphans {
+        let size = std::fs::metadata(&orphan).map(|m| m.len()).unwrap_or_default();
+
+        match std::fs::remove_file(&orphan) {
+            Ok(()) => {
+                removed += 1;
+                freed_bytes += size;
+            }
+            Err(e) => event!(Level::WARN, "Error removing {}: {e}", orphan.to_string_lossy()),
+        }
+    }
+
+    if removed > 0 {
+        event!(Level::DEBUG, removed, freed_bytes, "Removed orphaned files from the cache directory");
+    }
+}
+
+fn is_orphan(path: &Path) -> bool {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some(TEMP_EXTENSION) => true,
+        // <file>.meta belongs to <file>
+        Some(LEGACY_METADATA_EXTENSION) => !path.with_extension("").exists(),
+        _ => false,
+    }
+}
+
+/// Recognises the documents older versions cached
+fn legacy_document(path: &Path) -> Option<(u32, Source)> {
+    let app_id = path.file_stem()?.to_str()?.parse().ok()?;
+
+    match path.extension()?.to_str()? {
+        "html" => Some((app_id, Source::StorePage)),
+        "json" => Some((app_id, Source::StoreApi)),
+        _ => None,
+    }
+}
+
+fn legacy_metadata_path(cache_path: &Path) -> PathBuf {
+    let mut file_name = cache_path.file_name().unwrap_or_default().to_owned();
+    file_name.push(".");
+    file_name.push(LEGACY_METADATA_EXTENSION);
+
+    cache_path.with_file_name(file_name)
+}
+
+/// Reads the metadata of a cached document.
+///
+/// Some older versions did not store metadata, for those we use the file's modification time.
+fn read_legacy_metadata(cache_path: &Path) -> FetchMetadata {
+    let metadata = std::fs::read_to_string(legacy_metadata_path(cache_path))
+        .ok()
+        .and_then(|m| serde_json::from_str(&m).ok());
+
+    metadata.unwrap_or_else(|| FetchMetadata {
+        fetched_at: std::fs::metadata(cache_path)
+            .and_then(|m| m.modified())
+            .map(to_unix_time)
+            .unwrap_or_default(),
+        ..Default::default()
+    })
+}
+
+fn unix_now() -> i64 {
+    to_unix_time(SystemTime::now())
+}
+
+fn to_unix_time(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default()
+}
+
 /// Builds a document cache.
 ///
-/// Defaults to using `XDG_RUNTIME_DIR`.
+/// Defaults to using `XDG_CACHE_HOME`.
 pub struct DocumentCacheBuilder {
     location: Option<String>,
+    ttl: Duration,
+    limits: IndexLimits,
+    locale: StoreLocale,
+    age_gate: AgeGateAnswer,
+    steamgriddb_api_key: Option<String>,
+    http: config::Http,
 }
 
 impl DocumentCacheBuilder {
     /// Creates a new `DocumentCacheBuilder` with default options set.
     ///
-    /// The default location is whatever `XDG_RUNTIME_DIR` points to.
+    /// The default location is whatever `XDG_CACHE_HOME` points to, or `~/.cache` if it is not set.
     pub fn new() -> DocumentCacheBuilder {
-        DocumentCacheBuilder { location: None }
+        DocumentCacheBuilder {
+            location: None,
+            ttl: DEFAULT_TTL,
+            limits: IndexLimits { max_entries: DEFAULT_MAX_ENTRIES, max_bytes: DEFAULT_MAX_SIZE_BYTES },
+            locale: StoreLocale::default(),
+            age_gate: AgeGateAnswer::default(),
+            steamgriddb_api_key: None,
+            http: config::Http::default(),
+        }
     }
 
     /// Changes the location of the document cache
-    #[allow(dead_code)]
     pub fn with_location(mut self, path: &str) -> DocumentCacheBuilder {
         self.location = Some(path.to_owned());
         self
     }
 
+    /// Changes how long cached documents are used before they are revalidated
+    pub fn with_ttl(mut self, ttl: Duration) -> DocumentCacheBuilder {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Changes how many apps the cache keeps before it evicts the least recently used ones
+    pub fn with_max_entries(mut self, max_entries: usize) -> DocumentCacheBuilder {
+        self.limits.max_entries = max_entries;
+        self
+    }
+
+    /// Changes how large the cache's index may grow, in bytes, before it evicts the least recently used apps
+    pub fn with_max_size(mut self, max_bytes: u64) -> DocumentCacheBuilder {
+        self.limits.max_bytes = max_bytes;
+        self
+    }
+
+    /// Changes the language and country documents are downloaded in
+    pub fn with_locale(mut self, locale: StoreLocale) -> DocumentCacheBuilder {
+        self.locale = locale;
+        self
+    }
+
+    /// Changes how age gates on store pages are answered
+    pub fn with_age_gate(mut self, age_gate: AgeGateAnswer) -> DocumentCacheBuilder {
+        self.age_gate = age_gate;
+        self
+    }
+
+    /// Changes the key artwork is downloaded from SteamGridDB with
+    pub fn with_steamgriddb_api_key(mut self, api_key: Option<String>) -> DocumentCacheBuilder {
+        self.steamgriddb_api_key = api_key;
+        self
+    }
+
+    /// Changes the timeouts, retries and rate limit of downloads
+    pub fn with_http(mut self, http: config::Http) -> DocumentCacheBuilder {
+        self.http = http;
+        self
+    }
+
     /// Builds the document cache with the given options.
     ///
+    /// Caches from older versions in `XDG_RUNTIME_DIR` are moved to the new location.
+    ///
     /// This consumes the builder.
     pub fn build(self) -> Result<DocumentCache> {
-        match self.location {
+        let location = match self.location {
             Some(l) => create_cache_dir(l.as_str())
-                .with_context(|| "Error building document cache")
-                .map(DocumentCache::new),
-            None => create_cache_dir(
-                get_runtime_path()
-                    .expect("Could not determine XDG_RUNTIME_DIR")
-                    .as_str(),
-            )
-            .map(DocumentCache::new),
+                .with_context(|| "Error building document cache")?,
+            None => create_cache_dir(get_cache_home()?.to_string_lossy().as_ref())?,
+        };
+
+        if let Ok(runtime_path) = get_runtime_path() {
+            let mut runtime_cache = PathBuf::from(runtime_path);
+            runtime_cache.push(super::constants::APP_NAME);
+            runtime_cache.push(LEGACY_CACHE_DIR);
+
+            migrate_cache(&runtime_cache, Path::new(&location));
         }
+        migrate_cache(&Path::new(&location).join(LEGACY_CACHE_DIR), Path::new(&location));
+
+        DocumentCache::new(location, self.ttl, self.limits, self.locale, self.age_gate, self.steamgriddb_api_key, &self.http)
     }
 }
 
-/// Tries to detect or create a valid cache dir location
+/// Moves the files of an old cache directory to the new one and removes the old directory.
+///
+/// Files that already exist in the new location are kept. Errors are logged, as we can always download the files again.
+fn migrate_cache(old: &Path, new: &Path) {
+    if !old.is_dir() || same_dir(old, new) {
+        return;
+    }
+
+    event!(Level::INFO, "Moving cache from {} to {}", old.to_string_lossy(), new.to_string_lossy());
+
+    let entries = match std::fs::read_dir(old) {
+        Ok(e) => e,
+        Err(e) => {
+            event!(Level::WARN, "Error reading old cache directory: {e}");
+            return;
+        }
+    };
+
+    for entry in entries.filter_map(|e| e.ok()).filter(|e| e.path().is_file()) {
+        let target = new.join(entry.file_name());
+
+        // the old cache is usually on a tmpfs, so we cannot simply rename
+        if !target.exists() {
+            if let Err(e) = std::fs::copy(entry.path(), &target) {
+                event!(Level::WARN, "Error moving {} to the new cache: {e}", entry.path().to_string_lossy());
+                continue;
+            }
+        }
+
+        if let Err(e) = std::fs::remove_file(entry.path()) {
+            event!(Level::WARN, "Error removing {} from the old cache: {e}", entry.path().to_string_lossy());
+        }
+    }
+
+    if let Err(e) = std::fs::remove_dir(old) {
+        event!(Level::DEBUG, "Could not remove old cache directory: {e}");
+    }
+}
+
+fn same_dir(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// Creates the cache directory `discord-rpc-helper` in a base directory, and the base directory if it is missing.
+///
+/// If the base directory is already called `discord-rpc-helper`, it is used as is.
 fn create_cache_dir(path_str: &str) -> Result<String> {
-    let mut path = PathBuf::new();
-    path.push(path_str);
+    let mut path = PathBuf::from(path_str);
 
-    if !path.is_dir() {
-        // Also checks if the path exists
-        return Err(anyhow!(
-            "Path \"{}\" does not point to a directory.",
-            path_str
-        ));
+    if path.exists() && !path.is_dir() {
+        return Err(anyhow!("Path \"{}\" does not point to a directory.", path_str));
     }
 
-    if path.ends_with(super::constants::APP_NAME) {
-        path.push(CACHE_DIR);
-    } else {
+    if !path.ends_with(super::constants::APP_NAME) {
         path.push(super::constants::APP_NAME);
-        path.push(CACHE_DIR);
     }
 
-    match path.is_dir() {
-        true => Ok(path.to_string_lossy().to_string()),
-        false => std::fs::create_dir_all(&path)
-            .with_context(|| format!("Error creating directory '{}'", path.to_string_lossy()))
-            .and(Ok(path.to_string_lossy().to_string())),
+    std::fs::create_dir_all(&path)
+        .with_context(|| format!("Error creating directory '{}'", path.to_string_lossy()))?;
+
+    Ok(path.to_string_lossy().to_string())
+}
+
+/// Gets the base directory for caches: `XDG_CACHE_HOME`, or `~/.cache` if it is not set
+fn get_cache_home() -> Result<PathBuf> {
+    cache_home_from(std::env::var_os(XDG_CACHE_ENV_VAR), std::env::var_os(HOME_ENV_VAR))
+        .with_context(|| format!("Neither {XDG_CACHE_ENV_VAR} nor {HOME_ENV_VAR} is set"))
+}
+
+fn cache_home_from(xdg_cache_home: Option<OsString>, home: Option<OsString>) -> Option<PathBuf> {
+    // the spec says relative paths are invalid and should be ignored
+    match xdg_cache_home.map(PathBuf::from).filter(|p| p.is_absolute()) {
+        Some(cache_home) => Some(cache_home),
+        None => home.filter(|h| !h.is_empty()).map(|h| Path::new(&h).join(HOME_CACHE_DIR)),
     }
 }
 
@@ -376,11 +991,291 @@ mod tests {
         );
     }
 
+    #[test]
+    fn prefers_xdg_cache_home() {
+        let cache_home = cache_home_from(Some("/var/cache/user".into()), Some("/home/user".into()));
+
+        assert_eq!(cache_home, Some(PathBuf::from("/var/cache/user")));
+    }
+
+    #[test]
+    fn falls_back_to_home_cache() {
+        assert_eq!(cache_home_from(None, Some("/home/user".into())), Some(PathBuf::from("/home/user/.cache")));
+        assert_eq!(cache_home_from(Some("relative".into()), Some("/home/user".into())), Some(PathBuf::from("/home/user/.cache")));
+        assert_eq!(cache_home_from(None, None), None);
+    }
+
+    #[test]
+    fn creates_missing_cache_location() -> Result<()> {
+        let base = std::env::temp_dir().join(format!("{}-location-test-{}", super::super::constants::APP_NAME, std::process::id()));
+        let location = base.join("not/there/yet");
+
+        let cache_dir = create_cache_dir(location.to_string_lossy().as_ref())?;
+
+        assert_eq!(PathBuf::from(&cache_dir), location.join(super::super::constants::APP_NAME));
+        assert!(Path::new(&cache_dir).is_dir());
+
+        std::fs::remove_dir_all(base)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn migrates_old_cache() -> Result<()> {
+        let base = std::env::temp_dir().join(format!("{}-migration-test-{}", super::super::constants::APP_NAME, std::process::id()));
+        let old = base.join("old");
+        let new = base.join("new");
+        std::fs::create_dir_all(&old)?;
+        std::fs::create_dir_all(&new)?;
+        std::fs::write(old.join("570.html"), "old page")?;
+        std::fs::write(old.join("730.html"), "old page")?;
+        std::fs::write(new.join("730.html"), "new page")?;
+
+        migrate_cache(&old, &new);
+
+        assert!(!old.exists());
+        assert_eq!(std::fs::read_to_string(new.join("570.html"))?, "old page");
+        assert_eq!(std::fs::read_to_string(new.join("730.html"))?, "new page");
+
+        std::fs::remove_dir_all(base)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn finds_legacy_metadata_next_to_entry() {
+        let path = legacy_metadata_path(Path::new("/cache/570.html"));
+
+        assert_eq!(path, PathBuf::from("/cache/570.html.meta"));
+    }
+
+    #[test]
+    fn uses_modification_time_without_metadata() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("{}-metadata-test-{}.html", super::super::constants::APP_NAME, std::process::id()));
+        std::fs::write(&path, "page")?;
+
+        let metadata = read_legacy_metadata(&path);
+        std::fs::remove_file(&path)?;
+
+        assert!(unix_now() - metadata.fetched_at < 60);
+        assert!(metadata.etag.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn extracts_store_page_fields() {
+        let page = r#"<html><body>
+            <div class="apphub_AppIcon"><img src="https://example.com/icon.jpg"></div>
+            <div id="appHubAppName">Tom &amp; Jerry</div>
+        </body></html>"#;
+
+        let fields = extract_store_page(page);
+
+        assert_eq!(fields.name.as_deref(), Some("Tom & Jerry"));
+        assert_eq!(fields.icon_url.as_deref(), Some("https://example.com/icon.jpg"));
+    }
+
+    #[test]
+    fn recognises_store_page_urls() -> Result<()> {
+        assert!(is_store_page_of(&Url::parse("https://store.steampowered.com/app/570/Dota_2/")?, 570));
+        assert!(!is_store_page_of(&Url::parse("https://store.steampowered.com/app/5700/")?, 570));
+        assert!(!is_store_page_of(&Url::parse("https://store.steampowered.com/")?, 570));
+        assert!(!is_store_page_of(&Url::parse("https://store.steampowered.com/agecheck/app/570/")?, 570));
+
+        Ok(())
+    }
+
+    #[test]
+    fn fills_missing_fields_from_other_document() {
+        let localized = Extracted { header_image: Some("header_german.jpg".to_owned()), ..Default::default() };
+        let english = Extracted {
+            name: Some("Dota 2".to_owned()),
+            header_image: Some("header.jpg".to_owned()),
+            ..Default::default()
+        };
+
+        let fields = localized.or(english);
+
+        assert_eq!(fields.name.as_deref(), Some("Dota 2"));
+        assert_eq!(fields.header_image.as_deref(), Some("header_german.jpg"));
+    }
+
+    #[test]
+    fn asks_about_redirected_app_again_after_failure_ttl() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("{}-redirect-test-{}", super::super::constants::APP_NAME, std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let limits = IndexLimits { max_entries: DEFAULT_MAX_ENTRIES, max_bytes: DEFAULT_MAX_SIZE_BYTES };
+        let cache = DocumentCache::new(
+            dir.to_string_lossy().into_owned(),
+            DEFAULT_TTL,
+            limits,
+            StoreLocale::default(),
+            AgeGateAnswer::default(),
+            None,
+            &config::Http::default(),
+        )?;
+        let front_page = Url::parse("https://store.steampowered.com/")?;
+
+        let result = to_record(Source::StorePage, 570, Ok(Fetched::NotOnStore(front_page)), None);
+        let reason = format!("{:#}", result.unwrap_err());
+        cache.index.insert_failure(570, DEFAULT_LOCALE, Source::StorePage, Failure { failed_at: 100, reason });
+
+        let failure_ttl = FAILURE_TTL.as_secs() as i64;
+        let before = cache.lookup(Source::StorePage, 570, DEFAULT_LOCALE, 100 + failure_ttl - 1);
+        let after = cache.lookup(Source::StorePage, 570, DEFAULT_LOCALE, 100 + failure_ttl);
+        std::fs::remove_dir_all(dir)?;
+
+        assert!(matches!(before, Lookup::RecentFailure(f) if f.reason.contains("redirected")));
+        assert_eq!(after, Lookup::Missing);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn downloads_stale_records_again() -> Result<()> {
+        use std::io::{Read, Write};
+
+        let dir = std::env::temp_dir().join(format!("{}-refresh-test-{}", super::super::constants::APP_NAME, std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let limits = IndexLimits { max_entries: DEFAULT_MAX_ENTRIES, max_bytes: DEFAULT_MAX_SIZE_BYTES };
+        let cache = DocumentCache::new(
+            dir.to_string_lossy().into_owned(),
+            DEFAULT_TTL,
+            limits,
+            StoreLocale::default(),
+            AgeGateAnswer::default(),
+            None,
+            &config::Http::default(),
+        )?;
+        let stale = Record {
+            fields: Extracted { name: Some("Dota".to_owned()), ..Default::default() },
+            fetch: FetchMetadata { fetched_at: 100, etag: None, last_modified: None, status: 200 },
+        };
+        cache.index.insert(570, DEFAULT_LOCALE, Source::StoreApi, stale);
+
+        // serves the app details once
+        let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+        let url = format!("http://{}/api/appdetails?appids=570", listener.local_addr()?);
+        let server = std::thread::spawn(move || -> std::io::Result<()> {
+            let (mut stream, _) = listener.accept()?;
+            let mut request = Vec::new();
+            let mut buffer = [0; 1024];
+            while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                match stream.read(&mut buffer)? {
+                    0 => break,
+                    n => request.extend_from_slice(&buffer[..n]),
+                }
+            }
+
+            let body = r#"{ "570": { "success": true, "data": { "type": "game", "name": "Dota 2", "steam_appid": 570 } } }"#;
+            write!(stream, "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}", body.len())
+        });
+
+        // a fresh record is not downloaded
+        cache.refresh_record(Source::StoreApi, 570, DEFAULT_LOCALE, &url, 200).await;
+        let before = cache.index.get(570, DEFAULT_LOCALE, Source::StoreApi);
+
+        cache.refresh_record(Source::StoreApi, 570, DEFAULT_LOCALE, &url, 100 + DEFAULT_TTL.as_secs() as i64).await;
+        let after = cache.index.get(570, DEFAULT_LOCALE, Source::StoreApi);
+        server.join().unwrap()?;
+        std::fs::remove_dir_all(dir)?;
+
+        assert_eq!(before.and_then(|r| r.fields.name).as_deref(), Some("Dota"));
+        let after = after.with_context(|| "The record is gone")?;
+        assert_eq!(after.fields.name.as_deref(), Some("Dota 2"));
+        assert!(after.fetch.fetched_at > 100);
+
+        Ok(())
+    }
+
+    #[test]
+    fn indexes_unanswered_age_gates_without_fields() -> Result<()> {
+        let fetch = FetchMetadata { fetched_at: 100, etag: None, last_modified: None, status: 200 };
+
+        let record = to_record(Source::StorePage, 570, Ok(Fetched::AgeGated(fetch.clone())), None)?;
+
+        assert_eq!(record, Record { fields: Extracted::default(), fetch });
+
+        Ok(())
+    }
+
+    #[test]
+    fn does_not_index_apps_the_store_does_not_know() {
+        assert!(extract(Source::StoreApi, 570, r#"{"570":{"success":false}}"#).is_err());
+    }
+
+    #[test]
+    fn rejects_pages_without_name() {
+        let front_page = r#"<html><body><div class="home_page_content"></div></body></html>"#;
+
+        assert!(extract(Source::StorePage, 570, front_page).is_err());
+    }
+
+    #[test]
+    fn indexes_steamgriddb_artwork() -> Result<()> {
+        let icons = r#"{ "success": true, "data": [{ "id": 1, "url": "https://cdn2.steamgriddb.com/icon/a.png" }] }"#;
+
+        assert_eq!(extract(Source::SteamgriddbIcons, 570, icons)?.icon_url.as_deref(), Some("https://cdn2.steamgriddb.com/icon/a.png"));
+        assert_eq!(extract(Source::SteamgriddbGrids, 570, r#"{ "success": true, "data": [] }"#)?, Extracted::default());
+        // unknown apps are remembered as failures, not as records without artwork
+        assert!(extract(Source::SteamgriddbGrids, 570, r#"{ "success": false, "errors": ["Game not found"] }"#).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn migrates_documents_into_index() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("{}-index-test-{}", super::super::constants::APP_NAME, std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        std::fs::write(dir.join("570.html"), r#"<div id="appHubAppName">Dota 2</div>"#)?;
+        std::fs::write(dir.join("570.html.meta"), r#"{"fetched_at":100,"etag":"\"abc\"","last_modified":null,"status":200}"#)?;
+        std::fs::write(dir.join("730.json"), r#"{"730":{"success":true,"data":{"type":"game","name":"Counter-Strike 2","steam_appid":730}}}"#)?;
+
+        let limits = IndexLimits { max_entries: DEFAULT_MAX_ENTRIES, max_bytes: DEFAULT_MAX_SIZE_BYTES };
+        migrate_documents(&dir, &MetadataIndex::load(&dir, limits));
+        let index = MetadataIndex::load(&dir, limits);
+
+        let page = index.get(570, DEFAULT_LOCALE, Source::StorePage).unwrap();
+        assert_eq!(page.fields.name.as_deref(), Some("Dota 2"));
+        assert_eq!(page.fetch.etag.as_deref(), Some("\"abc\""));
+        assert_eq!(index.get(730, DEFAULT_LOCALE, Source::StoreApi).and_then(|r| r.fields.name).as_deref(), Some("Counter-Strike 2"));
+        assert!(!dir.join("570.html").exists());
+        assert!(!dir.join("570.html.meta").exists());
+        assert!(!dir.join("730.json").exists());
+
+        std::fs::remove_dir_all(dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn removes_orphaned_files() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("{}-orphans-test-{}", super::super::constants::APP_NAME, std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        for file in ["index.jsonl", "index.tmp", "cookies", "cookies.tmp", "570.html", "570.html.meta", "730.json.meta"] {
+            std::fs::write(dir.join(file), "")?;
+        }
+
+        remove_orphans(&dir);
+
+        let mut left: Vec<String> = std::fs::read_dir(&dir)?
+            .filter_map(|e| e.ok())
+            .map(|e| e.file_name().to_string_lossy().into_owned())
+            .collect();
+        left.sort();
+        std::fs::remove_dir_all(&dir)?;
+
+        assert_eq!(left, ["570.html", "570.html.meta", "cookies", "index.jsonl"]);
+
+        Ok(())
+    }
+
     #[test]
     #[ignore = "Running these automatically, they interfere because of the directories"]
     fn builds_with_default_location() -> Result<()> {
         let builder = DocumentCacheBuilder::new();
-        let runtime_path = get_runtime_path()?;
+        let cache_home = get_cache_home()?;
 
         let result = builder.build();
 
@@ -389,7 +1284,7 @@ mod tests {
             "Failed to build builder: {}",
             result.err().unwrap()
         );
-        assert!(result?.location == runtime_path);
+        assert_eq!(PathBuf::from(&result?.location), cache_home.join("discord-rpc-helper"));
 
         Ok(())
     }
@@ -406,7 +1301,7 @@ mod tests {
             "Failed to build builder: {}",
             result.err().unwrap()
         );
-        assert_eq!(result?.location, "./discord-rpc-helper/cache");
+        assert_eq!(result?.location, "./discord-rpc-helper");
 
         Ok(())
     }
@@ -0,0 +1,180 @@
+//! Persistence of the cookie jar, which keeps the store's session and age gate cookies between restarts.
+//!
+//! The jar is written atomically with only the user allowed to read it, since it can contain a logged-in session.
+
+use anyhow::{anyhow, Context, Result};
+use reqwest_cookie_store::CookieStore;
+use std::{
+    convert::Infallible,
+    fs::{File, OpenOptions},
+    io::{BufReader, Write},
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+};
+use tracing::{event, Level};
+
+const COOKIE_STORE_PATH: &str = "cookies";
+/// Older versions stored the jar in the deprecated JSON format of `cookie_store`
+const COOKIE_STORE_PATH_OLD: &str = "cookies.json";
+const TEMP_EXTENSION: &str = "tmp";
+const CORRUPT_EXTENSION: &str = "corrupt";
+const COOKIE_STORE_MODE: u32 = 0o600;
+
+/// Gets the path of the cookie jar in the cache directory
+pub fn cookie_store_path(cache_dir: &Path) -> PathBuf {
+    cache_dir.join(COOKIE_STORE_PATH)
+}
+
+/// Loads the cookie jar from the cache directory.
+///
+/// Jars of older versions are converted. A corrupted jar is moved aside and we start with an empty one.
+/// Expired cookies are not loaded.
+pub fn load(cache_dir: &Path) -> CookieStore {
+    let path = cookie_store_path(cache_dir);
+    let old_path = cache_dir.join(COOKIE_STORE_PATH_OLD);
+
+    let (store_path, result) = if path.exists() {
+        (path.clone(), read(&path, false))
+    } else if old_path.exists() {
+        (old_path.clone(), read(&old_path, true))
+    } else {
+        return CookieStore::new(None);
+    };
+
+    match result {
+        Ok(store) => {
+            if old_path.exists() {
+                if let Err(e) = std::fs::remove_file(&old_path) {
+                    event!(Level::WARN, "Error removing the old cookie jar: {e}");
+                }
+            }
+
+            store
+        }
+        Err(e) => {
+            event!(Level::WARN, "Starting with an empty cookie jar, {} is corrupted: {e:?}", store_path.to_string_lossy());
+
+            if let Err(e) = std::fs::rename(&store_path, store_path.with_extension(CORRUPT_EXTENSION)) {
+                event!(Level::WARN, "Error moving the corrupted cookie jar aside: {e}");
+            }
+
+            CookieStore::new(None)
+        }
+    }
+}
+
+fn read(path: &Path, legacy: bool) -> Result<CookieStore> {
+    let reader = BufReader::new(File::open(path)?);
+
+    let store = match legacy {
+        // deprecated, but this is for migration purposes only. Will be removed at a later date
+        #[allow(deprecated)]
+        true => CookieStore::load_json(reader),
+        false => CookieStore::load(reader, |c| serde_json::from_str(c)),
+    };
+
+    store.map_err(|e| anyhow!(e))
+}
+
+/// Drops expired cookies from the jar
+pub fn prune(store: &mut CookieStore) {
+    let unexpired = store.iter_unexpired().cloned().map(Ok::<_, Infallible>);
+
+    let Ok(pruned) = CookieStore::from_cookies(unexpired, false);
+    *store = pruned;
+}
+
+/// Serializes the persistent cookies of the jar, in the format [load] reads
+pub fn serialize(store: &CookieStore) -> Result<String> {
+    let mut contents = Vec::new();
+
+    store
+        .save(&mut contents, serde_json::to_string)
+        .map_err(|e| anyhow!(e))?;
+
+    Ok(String::from_utf8(contents)?)
+}
+
+/// Writes a serialized jar to a temporary file and moves it over the old jar
+pub fn write(cache_dir: &Path, contents: &str) -> Result<()> {
+    let path = cookie_store_path(cache_dir);
+    let temp_path = path.with_extension(TEMP_EXTENSION);
+
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(COOKIE_STORE_MODE)
+        .open(&temp_path)
+        .with_context(|| format!("Error creating {}", temp_path.to_string_lossy()))?;
+
+    file.write_all(contents.as_bytes())?;
+    file.sync_all()?;
+
+    std::fs::rename(&temp_path, &path).with_context(|| "Error replacing the cookie jar")
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+    use url::Url;
+
+    fn temp_dir(name: &str) -> Result<PathBuf> {
+        let dir = std::env::temp_dir().join(format!("{}-cookies-{name}-{}", crate::constants::APP_NAME, std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        Ok(dir)
+    }
+
+    #[test]
+    fn roundtrips_persistent_cookies() -> Result<()> {
+        let dir = temp_dir("roundtrip")?;
+        let url = Url::parse("https://store.steampowered.com/")?;
+        let mut store = CookieStore::new(None);
+        store.parse("birthtime=631152000; Path=/; Max-Age=3600", &url)?;
+        store.parse("session_only=1; Path=/", &url)?;
+
+        write(&dir, &serialize(&store)?)?;
+        let loaded = load(&dir);
+        let mode = std::fs::metadata(cookie_store_path(&dir))?.permissions().mode();
+        std::fs::remove_dir_all(&dir)?;
+
+        assert!(loaded.contains("store.steampowered.com", "/", "birthtime"));
+        assert!(!loaded.contains("store.steampowered.com", "/", "session_only"));
+        assert_eq!(mode & 0o777, COOKIE_STORE_MODE);
+
+        Ok(())
+    }
+
+    #[test]
+    fn recovers_from_corrupted_jar() -> Result<()> {
+        let dir = temp_dir("corrupted")?;
+        std::fs::write(cookie_store_path(&dir), "{ not a cookie")?;
+
+        let store = load(&dir);
+        let moved_aside = cookie_store_path(&dir).with_extension(CORRUPT_EXTENSION).exists();
+        std::fs::remove_dir_all(&dir)?;
+
+        assert_eq!(store.iter_any().count(), 0);
+        assert!(moved_aside);
+
+        Ok(())
+    }
+
+    #[test]
+    fn prunes_expired_cookies() -> Result<()> {
+        // the store refuses to take expired cookies, so they have to expire after being stored
+        let jar = r#"{"raw_cookie":"fresh=1; Path=/","path":["/",true],"domain":{"HostOnly":"store.steampowered.com"},"expires":{"AtUtc":"2100-01-01T00:00:00Z"}}
+{"raw_cookie":"stale=1; Path=/","path":["/",true],"domain":{"HostOnly":"store.steampowered.com"},"expires":{"AtUtc":"2000-01-01T00:00:00Z"}}"#;
+        let mut store = CookieStore::load_all(jar.as_bytes(), |c| serde_json::from_str(c)).map_err(|e| anyhow!(e))?;
+        assert_eq!(store.iter_any().count(), 2);
+
+        prune(&mut store);
+
+        assert_eq!(store.iter_any().count(), 1);
+        assert!(store.contains("store.steampowered.com", "/", "fresh"));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,566 @@
+//! Compact on-disk index of the metadata we extract from Steam and SteamGridDB.
+//!
+//! The index is a JSON lines file: a header with the schema version, followed by one entry per app and store locale.
+//! It is loaded into memory once, and changes are written back as a whole with [MetadataIndex::flush], at most once per
+//! interval, and at shutdown. Writes go to a temporary file that replaces the index once it is complete, so a crash
+//! never leaves a half-written index behind.
+//! Before every write, the least recently used entries beyond the configured limits are evicted.
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tokio::io::AsyncWriteExt;
+use tracing::{event, Level};
+
+/// Version of the index format. Indexes with another version are discarded.
+pub const INDEX_SCHEMA_VERSION: u32 = 1;
+const INDEX_FILE: &str = "index.jsonl";
+const TEMP_EXTENSION: &str = "tmp";
+
+/// How large the index may grow before entries are evicted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IndexLimits {
+    pub max_entries: usize,
+    /// Size of the index file in bytes
+    pub max_bytes: u64,
+}
+
+/// What an eviction removed, and what is left
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EvictionStats {
+    pub evicted: usize,
+    pub freed_bytes: u64,
+    pub entries: usize,
+    pub bytes: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct IndexHeader {
+    schema_version: u32,
+}
+
+/// Where a record's fields were extracted from
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Source {
+    /// The store's `appdetails` API
+    StoreApi,
+    /// The scraped store page
+    StorePage,
+    /// The icons of SteamGridDB
+    SteamgriddbIcons,
+    /// The grids of SteamGridDB, large images like the store header
+    SteamgriddbGrids,
+}
+
+/// Information about a download, used to revalidate it later
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct FetchMetadata {
+    /// Unix timestamp (seconds) of when the document was last downloaded or revalidated
+    pub fetched_at: i64,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    pub status: u16,
+}
+
+/// The fields we use, extracted from a downloaded document
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Extracted {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub app_type: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub icon_url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub header_image: Option<String>,
+}
+
+/// The fields extracted from one source, with the metadata of its download
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Record {
+    #[serde(flatten)]
+    pub fields: Extracted,
+    pub fetch: FetchMetadata,
+}
+
+/// A failed download, remembered for a short while so we do not ask the store again on every lookup
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Failure {
+    /// Unix timestamp (seconds) of when the download failed
+    pub failed_at: i64,
+    pub reason: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+struct IndexEntry {
+    app_id: u32,
+    /// The query parameters of the store locale the documents were downloaded in, empty for the store's default
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    locale: String,
+    /// Unix timestamp (seconds) of the last lookup, for evicting the least recently used entries
+    #[serde(default)]
+    last_used: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    store_api: Option<Record>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    store_page: Option<Record>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    steamgriddb_icons: Option<Record>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    steamgriddb_grids: Option<Record>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    failures: HashMap<Source, Failure>,
+}
+
+impl IndexEntry {
+    fn record(&self, source: Source) -> &Option<Record> {
+        match source {
+            Source::StoreApi => &self.store_api,
+            Source::StorePage => &self.store_page,
+            Source::SteamgriddbIcons => &self.steamgriddb_icons,
+            Source::SteamgriddbGrids => &self.steamgriddb_grids,
+        }
+    }
+
+    fn record_mut(&mut self, source: Source) -> &mut Option<Record> {
+        match source {
+            Source::StoreApi => &mut self.store_api,
+            Source::StorePage => &mut self.store_page,
+            Source::SteamgriddbIcons => &mut self.steamgriddb_icons,
+            Source::SteamgriddbGrids => &mut self.steamgriddb_grids,
+        }
+    }
+}
+
+/// Entries are kept apart by AppId and store locale
+type IndexKey = (u32, String);
+
+/// The in-memory index, backed by `index.jsonl` in the cache directory
+#[derive(Debug)]
+pub struct MetadataIndex {
+    path: PathBuf,
+    entries: Mutex<HashMap<IndexKey, IndexEntry>>,
+    limits: IndexLimits,
+    /// Makes sure only one task writes the index file at a time
+    save_lock: tokio::sync::Mutex<()>,
+    /// Records or failures were added since the index was last written
+    dirty: AtomicBool,
+    /// When the index was last written by [MetadataIndex::flush] or [MetadataIndex::save]
+    saved_at: Mutex<Option<Instant>>,
+}
+
+impl MetadataIndex {
+    /// Loads the index from the cache directory. A missing, outdated or corrupted index starts out empty.
+    pub fn load(cache_dir: &Path, limits: IndexLimits) -> Self {
+        let path = cache_dir.join(INDEX_FILE);
+
+        let entries = match std::fs::read_to_string(&path) {
+            Ok(contents) => from_jsonl(&contents).unwrap_or_else(|e| {
+                event!(Level::WARN, "Discarding metadata index: {e:?}");
+                HashMap::new()
+            }),
+            Err(_) => HashMap::new(),
+        };
+
+        Self {
+            path,
+            entries: Mutex::new(entries),
+            limits,
+            save_lock: tokio::sync::Mutex::new(()),
+            dirty: AtomicBool::new(false),
+            saved_at: Mutex::new(None),
+        }
+    }
+
+    /// Remembers that an app was looked up, so it is evicted after apps that were not
+    pub fn touch(&self, app_id: u32, locale: &str) {
+        if let Some(entry) = self.entries.lock().unwrap().get_mut(&(app_id, locale.to_owned())) {
+            entry.last_used = unix_now();
+        }
+    }
+
+    /// Gets the record of an app from the given source
+    pub fn get(&self, app_id: u32, locale: &str, source: Source) -> Option<Record> {
+        self.entries
+            .lock()
+            .unwrap()
+            .get(&(app_id, locale.to_owned()))
+            .and_then(|e| e.record(source).clone())
+    }
+
+    /// Checks if the index has a record of an app from the given source
+    pub fn contains(&self, app_id: u32, locale: &str, source: Source) -> bool {
+        self.get(app_id, locale, source).is_some()
+    }
+
+    /// Gets the last failed download of an app from the given source, if it failed after the last success
+    pub fn failure(&self, app_id: u32, locale: &str, source: Source) -> Option<Failure> {
+        self.entries
+            .lock()
+            .unwrap()
+            .get(&(app_id, locale.to_owned()))
+            .and_then(|e| e.failures.get(&source).cloned())
+    }
+
+    /// Adds or replaces the record of an app from the given source. Call [MetadataIndex::flush] to persist it.
+    pub fn insert(&self, app_id: u32, locale: &str, source: Source, record: Record) {
+        let mut entries = self.entries.lock().unwrap();
+        let entry = Self::entry(&mut entries, app_id, locale);
+
+        *entry.record_mut(source) = Some(record);
+        entry.failures.remove(&source);
+        entry.last_used = unix_now();
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    /// Remembers a failed download. The record from an earlier download, if any, is kept.
+    pub fn insert_failure(&self, app_id: u32, locale: &str, source: Source, failure: Failure) {
+        let mut entries = self.entries.lock().unwrap();
+        let entry = Self::entry(&mut entries, app_id, locale);
+
+        entry.failures.insert(source, failure);
+        entry.last_used = unix_now();
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    fn entry<'a>(entries: &'a mut HashMap<IndexKey, IndexEntry>, app_id: u32, locale: &str) -> &'a mut IndexEntry {
+        entries.entry((app_id, locale.to_owned())).or_insert_with(|| IndexEntry {
+            app_id,
+            locale: locale.to_owned(),
+            ..Default::default()
+        })
+    }
+
+    /// Writes the index to disk if records or failures were added since the last write,
+    /// and the last write was at least `min_interval` ago.
+    ///
+    /// Returns whether the index was written.
+    pub async fn flush(&self, min_interval: Duration) -> Result<bool> {
+        let recently_saved = self.saved_at.lock().unwrap().is_some_and(|t| t.elapsed() < min_interval);
+
+        if recently_saved || !self.dirty.load(Ordering::Relaxed) {
+            return Ok(false);
+        }
+
+        self.save().await.map(|_| true)
+    }
+
+    /// Evicts entries beyond the limits and writes the index to disk
+    pub async fn save(&self) -> Result<()> {
+        let _guard = self.save_lock.lock().await;
+        let contents = self.evict_and_serialize()?;
+        let temp_path = self.path.with_extension(TEMP_EXTENSION);
+
+        let written = async {
+            let mut file = tokio::fs::File::create(&temp_path).await?;
+            file.write_all(contents.as_bytes()).await?;
+            file.sync_all().await?;
+
+            tokio::fs::rename(&temp_path, &self.path)
+                .await
+                .with_context(|| "Error replacing the metadata index")
+        }
+        .await;
+
+        self.saved(written.is_ok());
+        written
+    }
+
+    /// Evicts entries beyond the limits and writes the index to disk, for use outside of the async runtime.
+    ///
+    /// Fails if the index is being written by [MetadataIndex::save] at the same time.
+    pub fn save_blocking(&self) -> Result<()> {
+        let Ok(_guard) = self.save_lock.try_lock() else {
+            return Err(anyhow!("The metadata index is already being written"));
+        };
+        let contents = self.evict_and_serialize()?;
+        let temp_path = self.path.with_extension(TEMP_EXTENSION);
+
+        let written = (|| {
+            let mut file = std::fs::File::create(&temp_path)?;
+            file.write_all(contents.as_bytes())?;
+            file.sync_all()?;
+
+            std::fs::rename(&temp_path, &self.path).with_context(|| "Error replacing the metadata index")
+        })();
+
+        self.saved(written.is_ok());
+        written
+    }
+
+    /// Takes note of a write, so a failed one is tried again with the next flush
+    fn saved(&self, success: bool) {
+        match success {
+            true => *self.saved_at.lock().unwrap() = Some(Instant::now()),
+            false => self.dirty.store(true, Ordering::Relaxed),
+        }
+    }
+
+    fn evict_and_serialize(&self) -> Result<String> {
+        let mut entries = self.entries.lock().unwrap();
+        let stats = evict(&mut entries, self.limits)?;
+
+        event!(Level::DEBUG, entries = stats.entries, bytes = stats.bytes, "Writing the metadata index");
+        if stats.evicted > 0 {
+            event!(
+                Level::DEBUG,
+                evicted = stats.evicted,
+                freed_bytes = stats.freed_bytes,
+                "Evicted the least recently used entries from the metadata index"
+            );
+        }
+
+        // changes made from here on are written with the next flush
+        self.dirty.store(false, Ordering::Relaxed);
+        to_jsonl(&entries)
+    }
+}
+
+/// Removes the least recently used entries until the index is within its limits
+fn evict(entries: &mut HashMap<IndexKey, IndexEntry>, limits: IndexLimits) -> Result<EvictionStats> {
+    let mut sizes = entries
+        .iter()
+        .map(|(key, e)| Ok((e.last_used, key.clone(), serde_json::to_string(e)?.len() as u64 + 1)))
+        .collect::<Result<Vec<(i64, IndexKey, u64)>>>()?;
+    // oldest first
+    sizes.sort_unstable();
+
+    let header = serde_json::to_string(&IndexHeader { schema_version: INDEX_SCHEMA_VERSION })?.len() as u64 + 1;
+    let mut stats = EvictionStats {
+        bytes: header + sizes.iter().map(|(_, _, size)| size).sum::<u64>(),
+        ..Default::default()
+    };
+
+    for (_, key, size) in sizes {
+        if entries.len() <= limits.max_entries && stats.bytes <= limits.max_bytes {
+            break;
+        }
+
+        entries.remove(&key);
+        stats.evicted += 1;
+        stats.freed_bytes += size;
+        stats.bytes -= size;
+    }
+
+    stats.entries = entries.len();
+
+    Ok(stats)
+}
+
+fn to_jsonl(entries: &HashMap<IndexKey, IndexEntry>) -> Result<String> {
+    let mut sorted: Vec<&IndexEntry> = entries.values().collect();
+    sorted.sort_by(|a, b| (a.app_id, &a.locale).cmp(&(b.app_id, &b.locale)));
+
+    let mut contents = serde_json::to_string(&IndexHeader { schema_version: INDEX_SCHEMA_VERSION })?;
+    contents.push('\n');
+
+    for entry in sorted {
+        contents.push_str(&serde_json::to_string(entry)?);
+        contents.push('\n');
+    }
+
+    Ok(contents)
+}
+
+fn from_jsonl(contents: &str) -> Result<HashMap<IndexKey, IndexEntry>> {
+    let mut lines = contents.lines().filter(|l| !l.trim().is_empty());
+
+    let header: IndexHeader = serde_json::from_str(lines.next().unwrap_or_default())
+        .with_context(|| "The metadata index has no valid header")?;
+
+    if header.schema_version != INDEX_SCHEMA_VERSION {
+        return Err(anyhow!(
+            "The metadata index has schema version {}, expected {INDEX_SCHEMA_VERSION}",
+            header.schema_version
+        ));
+    }
+
+    lines
+        .map(|l| serde_json::from_str::<IndexEntry>(l).map(|e| ((e.app_id, e.locale.clone()), e)))
+        .collect::<Result<_, _>>()
+        .with_context(|| "The metadata index contains an invalid entry")
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    const UNLIMITED: IndexLimits = IndexLimits { max_entries: usize::MAX, max_bytes: u64::MAX };
+
+    fn record(name: &str) -> Record {
+        Record {
+            fields: Extracted {
+                name: Some(name.to_owned()),
+                ..Default::default()
+            },
+            fetch: FetchMetadata {
+                fetched_at: 100,
+                etag: Some("\"abc\"".to_owned()),
+                ..Default::default()
+            },
+        }
+    }
+
+    #[test]
+    fn roundtrips_entries() -> Result<()> {
+        let mut entries = HashMap::new();
+        entries.insert((570, String::new()), IndexEntry { app_id: 570, store_api: Some(record("Dota 2")), ..Default::default() });
+        entries.insert((570, "l=german".to_owned()), IndexEntry {
+            app_id: 570,
+            locale: "l=german".to_owned(),
+            store_api: Some(record("Dota 2")),
+            ..Default::default()
+        });
+        entries.insert((730, String::new()), IndexEntry { app_id: 730, store_page: Some(record("Counter-Strike 2")), ..Default::default() });
+        entries.insert((1, String::new()), IndexEntry {
+            app_id: 1,
+            failures: HashMap::from([(Source::StorePage, Failure { failed_at: 100, reason: "429".to_owned() })]),
+            ..Default::default()
+        });
+
+        let contents = to_jsonl(&entries)?;
+
+        assert_eq!(contents.lines().count(), 5);
+        assert_eq!(from_jsonl(&contents)?, entries);
+
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_other_schema_versions() {
+        let contents = r#"{"schema_version":0}
+{"app_id":570}
+"#;
+
+        assert!(from_jsonl(contents).is_err());
+    }
+
+    #[test]
+    fn rejects_missing_header() {
+        assert!(from_jsonl(r#"{"app_id":570}"#).is_err());
+    }
+
+    #[test]
+    fn keeps_sources_apart() {
+        let index = MetadataIndex {
+            path: PathBuf::new(),
+            entries: Mutex::new(HashMap::new()),
+            limits: UNLIMITED,
+            save_lock: tokio::sync::Mutex::new(()),
+            dirty: AtomicBool::new(false),
+            saved_at: Mutex::new(None),
+        };
+
+        index.insert(570, "", Source::StorePage, record("Dota 2"));
+
+        assert_eq!(index.get(570, "", Source::StorePage), Some(record("Dota 2")));
+        assert!(!index.contains(570, "", Source::StoreApi));
+        assert!(!index.contains(570, "l=german", Source::StorePage));
+    }
+
+    #[test]
+    fn clears_failure_on_success() {
+        let index = MetadataIndex {
+            path: PathBuf::new(),
+            entries: Mutex::new(HashMap::new()),
+            limits: UNLIMITED,
+            save_lock: tokio::sync::Mutex::new(()),
+            dirty: AtomicBool::new(false),
+            saved_at: Mutex::new(None),
+        };
+
+        index.insert_failure(570, "", Source::StorePage, Failure { failed_at: 100, reason: "503".to_owned() });
+        assert!(index.failure(570, "", Source::StorePage).is_some());
+        assert!(!index.contains(570, "", Source::StorePage));
+
+        index.insert(570, "", Source::StorePage, record("Dota 2"));
+        assert!(index.failure(570, "", Source::StorePage).is_none());
+    }
+
+    #[tokio::test]
+    async fn flushes_changes_at_most_once_per_interval() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("{}-index-{}", crate::constants::APP_NAME, std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let index = MetadataIndex::load(&dir, UNLIMITED);
+        let interval = Duration::from_secs(60);
+
+        assert!(!index.flush(interval).await?, "nothing changed yet");
+
+        index.insert(570, "", Source::StoreApi, record("Dota 2"));
+        assert!(index.flush(interval).await?);
+
+        index.insert(730, "", Source::StoreApi, record("Counter-Strike 2"));
+        assert!(!index.flush(interval).await?, "the index was just written");
+        assert!(index.flush(Duration::ZERO).await?);
+        assert!(!index.flush(Duration::ZERO).await?, "nothing changed since");
+
+        let reloaded = MetadataIndex::load(&dir, UNLIMITED);
+        std::fs::remove_dir_all(&dir)?;
+        assert!(reloaded.contains(730, "", Source::StoreApi));
+
+        Ok(())
+    }
+
+    fn entries_used_at(times: &[(u32, i64)]) -> HashMap<IndexKey, IndexEntry> {
+        times
+            .iter()
+            .map(|&(app_id, last_used)| ((app_id, String::new()), IndexEntry { app_id, last_used, store_page: Some(record("Game")), ..Default::default() }))
+            .collect()
+    }
+
+    #[test]
+    fn evicts_least_recently_used_entries() -> Result<()> {
+        let mut entries = entries_used_at(&[(570, 300), (730, 100), (440, 200)]);
+
+        let stats = evict(&mut entries, IndexLimits { max_entries: 2, max_bytes: u64::MAX })?;
+
+        assert_eq!(stats.evicted, 1);
+        assert_eq!(stats.entries, 2);
+        assert!(!entries.contains_key(&(730, String::new())));
+        assert_eq!(stats.bytes, to_jsonl(&entries)?.len() as u64);
+
+        Ok(())
+    }
+
+    #[test]
+    fn evicts_down_to_size() -> Result<()> {
+        let mut entries = entries_used_at(&[(570, 300), (730, 100), (440, 200)]);
+        let max_bytes = to_jsonl(&entries_used_at(&[(570, 300)]))?.len() as u64;
+
+        let stats = evict(&mut entries, IndexLimits { max_entries: usize::MAX, max_bytes })?;
+
+        assert_eq!(stats.evicted, 2);
+        assert_eq!(entries.keys().collect::<Vec<_>>(), vec![&(570, String::new())]);
+        assert!(stats.bytes <= max_bytes);
+
+        Ok(())
+    }
+
+    #[test]
+    fn keeps_entries_within_limits() -> Result<()> {
+        let mut entries = entries_used_at(&[(570, 300), (730, 100)]);
+
+        assert_eq!(evict(&mut entries, UNLIMITED)?.evicted, 0);
+        assert_eq!(entries.len(), 2);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,82 @@
+//! De-duplication of concurrent operations, so callers that need the same thing at the same time share one run.
+
+use std::{
+    collections::HashMap,
+    future::Future,
+    hash::Hash,
+    sync::{Arc, Mutex},
+};
+use tokio::sync::OnceCell;
+
+/// Operations that are running, by key
+#[derive(Debug)]
+pub struct InFlight<K, V> {
+    running: Mutex<HashMap<K, Arc<OnceCell<V>>>>,
+}
+
+impl<K: Eq + Hash + Clone, V: Clone> InFlight<K, V> {
+    pub fn new() -> Self {
+        Self {
+            running: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Runs an operation, unless one with the same key is already running. Then its result is awaited instead.
+    ///
+    /// If the caller that runs the operation is cancelled, one of the waiting callers runs it again.
+    pub async fn run<F, Fut>(&self, key: K, operation: F) -> V
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = V>,
+    {
+        let cell = Arc::clone(self.running.lock().unwrap().entry(key.clone()).or_default());
+
+        let value = cell.get_or_init(operation).await.clone();
+
+        // later callers run the operation again, unless another caller already started a new run
+        let mut running = self.running.lock().unwrap();
+        if running.get(&key).is_some_and(|c| Arc::ptr_eq(c, &cell)) {
+            running.remove(&key);
+        }
+
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    #[tokio::test]
+    async fn shares_running_operation() {
+        let in_flight = InFlight::new();
+        let runs = AtomicUsize::new(0);
+        let operation = || async {
+            runs.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            "Dota 2".to_owned()
+        };
+
+        let (first, second) = tokio::join!(in_flight.run(570, operation), in_flight.run(570, operation));
+
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        assert_eq!(first, second);
+        assert!(in_flight.running.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn runs_again_after_completion() {
+        let in_flight = InFlight::new();
+        let runs = AtomicUsize::new(0);
+        let operation = || async { runs.fetch_add(1, Ordering::SeqCst) };
+
+        assert_eq!(in_flight.run(570, operation).await, 0);
+        assert_eq!(in_flight.run(570, operation).await, 1);
+        assert_eq!(in_flight.run(730, operation).await, 2);
+    }
+}
@@ -0,0 +1,315 @@
+//! Access to the local Steam installation: library folders and app manifests.
+
+use super::vdf::{self, KeyValue};
+use anyhow::{Context, Result};
+use once_cell::sync::Lazy;
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::SystemTime,
+};
+use tracing::debug;
+
+/// Locations of the Steam installation, relative to the home directory
+const STEAM_ROOTS: [&str; 3] = [
+    ".steam/steam",
+    ".local/share/Steam",
+    ".var/app/com.valvesoftware.Steam/.local/share/Steam",
+];
+const STEAMAPPS_DIR: &str = "steamapps";
+const LIBRARY_FOLDERS_FILE: &str = "libraryfolders.vdf";
+const MANIFEST_PREFIX: &str = "appmanifest_";
+const MANIFEST_EXTENSION: &str = ".acf";
+
+/// `StateFlags` bits that mean Steam is working on the app's files
+const STATE_UPDATE_RUNNING: u64 = 0x100;
+const STATE_UPDATE_PAUSED: u64 = 0x200;
+const STATE_UPDATE_STARTED: u64 = 0x400;
+const STATE_PREALLOCATING: u64 = 0x80000;
+const STATE_DOWNLOADING: u64 = 0x100000;
+const STATE_STAGING: u64 = 0x200000;
+const STATE_COMMITTING: u64 = 0x400000;
+const STATE_UPDATING_MASK: u64 = STATE_UPDATE_RUNNING
+    | STATE_UPDATE_STARTED
+    | STATE_PREALLOCATING
+    | STATE_DOWNLOADING
+    | STATE_STAGING
+    | STATE_COMMITTING;
+
+/// The parts of an `appmanifest_<appid>.acf` we use
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AppManifest {
+    pub app_id: u32,
+    pub name: Option<String>,
+    pub state_flags: u64,
+    pub bytes_to_download: u64,
+    pub bytes_downloaded: u64,
+}
+
+impl AppManifest {
+    /// Parses the contents of an app manifest
+    pub fn parse(document: &str) -> Result<AppManifest> {
+        let kv = vdf::parse(document)?;
+        let state = kv.get("AppState").with_context(|| "App manifest has no AppState")?;
+
+        Ok(AppManifest {
+            app_id: state
+                .get_u64(&["appid"])
+                .and_then(|id| u32::try_from(id).ok())
+                .with_context(|| "App manifest has no valid appid")?,
+            name: state.get_str(&["name"]).map(str::to_owned),
+            state_flags: state.get_u64(&["StateFlags"]).unwrap_or_default(),
+            bytes_to_download: state.get_u64(&["BytesToDownload"]).unwrap_or_default(),
+            bytes_downloaded: state.get_u64(&["BytesDownloaded"]).unwrap_or_default(),
+        })
+    }
+
+    /// Returns true if Steam is currently downloading or applying an update
+    pub fn is_updating(&self) -> bool {
+        self.state_flags & STATE_UPDATING_MASK != 0 && self.state_flags & STATE_UPDATE_PAUSED == 0
+    }
+
+    /// The download progress in percent, if anything is being downloaded
+    pub fn download_progress(&self) -> Option<u8> {
+        match self.bytes_to_download {
+            0 => None,
+            total => Some((self.bytes_downloaded.min(total) * 100 / total) as u8),
+        }
+    }
+}
+
+/// Finds the root directories of the Steam installations of the current user
+pub fn steam_roots() -> Vec<PathBuf> {
+    let Some(home) = std::env::var_os("HOME") else {
+        return vec![];
+    };
+
+    let mut seen = HashSet::new();
+
+    // ~/.steam/steam is usually a symlink to one of the other locations
+    STEAM_ROOTS
+        .iter()
+        .map(|r| Path::new(&home).join(r))
+        .filter_map(|p| p.canonicalize().ok())
+        .filter(|p| p.join(STEAMAPPS_DIR).is_dir())
+        .filter(|p| seen.insert(p.clone()))
+        .collect()
+}
+
+/// Finds the `steamapps` directories of all Steam libraries
+pub fn library_folders() -> Vec<PathBuf> {
+    let mut seen = HashSet::new();
+
+    steam_roots()
+        .iter()
+        .flat_map(|root| {
+            let steamapps = root.join(STEAMAPPS_DIR);
+            let mut folders = vec![steamapps.clone()];
+
+            match fs::read_to_string(steamapps.join(LIBRARY_FOLDERS_FILE)) {
+                Ok(document) => match parse_library_folders(&document) {
+                    Ok(paths) => folders.extend(paths.iter().map(|p| p.join(STEAMAPPS_DIR))),
+                    Err(e) => debug!("Error parsing {LIBRARY_FOLDERS_FILE}: {e:?}"),
+                },
+                Err(e) => debug!("Error reading {LIBRARY_FOLDERS_FILE}: {e}"),
+            }
+
+            folders
+        })
+        .filter_map(|p| p.canonicalize().ok())
+        .filter(|p| seen.insert(p.clone()))
+        .collect()
+}
+
+/// Reads the library paths from `libraryfolders.vdf`
+fn parse_library_folders(document: &str) -> Result<Vec<PathBuf>> {
+    let kv = vdf::parse(document)?;
+    let folders = kv
+        .get("libraryfolders")
+        .with_context(|| "libraryfolders.vdf has no libraryfolders section")?;
+
+    Ok(folders
+        .children()
+        .iter()
+        // libraries are numbered, older versions also have some settings in this section
+        .filter(|(key, _)| key.parse::<u32>().is_ok())
+        .filter_map(|(_, folder)| match folder {
+            // older versions only list the path
+            KeyValue::Value(path) => Some(PathBuf::from(path)),
+            section => section.get_str(&["path"]).map(PathBuf::from),
+        })
+        .collect())
+}
+
+/// Parsed app manifests by path, with the modification time of the file they were parsed from
+type ManifestCache = HashMap<PathBuf, (SystemTime, AppManifest)>;
+
+static MANIFESTS: Lazy<Mutex<ManifestCache>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Reads the app manifests of all installed apps in all libraries.
+///
+/// Manifests are only parsed again when they changed since the last call. This still does blocking file system access,
+/// so call it with `spawn_blocking` from async code.
+pub fn app_manifests() -> Vec<AppManifest> {
+    let mut cache = MANIFESTS.lock().unwrap();
+    let mut current = ManifestCache::new();
+
+    let manifests = library_folders()
+        .iter()
+        .flat_map(|folder| read_manifests(folder, &cache, &mut current))
+        .collect();
+
+    // manifests of uninstalled apps are forgotten
+    *cache = current;
+
+    manifests
+}
+
+/// Reads the manifest of an installed app, without reading those of the other apps
+pub fn app_manifest(app_id: u32) -> Option<AppManifest> {
+    library_folders().iter().find_map(|folder| {
+        let document = fs::read_to_string(folder.join(format!("{MANIFEST_PREFIX}{app_id}{MANIFEST_EXTENSION}"))).ok()?;
+
+        AppManifest::parse(&document)
+            .inspect_err(|e| debug!("Error parsing the manifest of app {app_id}: {e:?}"))
+            .ok()
+    })
+}
+
+/// Reads the manifests in a `steamapps` folder, reusing those from `previous` that did not change.
+/// All manifests that were read are added to `current`.
+fn read_manifests(folder: &Path, previous: &ManifestCache, current: &mut ManifestCache) -> Vec<AppManifest> {
+    let Ok(entries) = fs::read_dir(folder) else {
+        return vec![];
+    };
+
+    entries
+        .filter_map(|e| e.ok())
+        .filter(|e| {
+            let name = e.file_name();
+            let name = name.to_string_lossy();
+            name.starts_with(MANIFEST_PREFIX) && name.ends_with(MANIFEST_EXTENSION)
+        })
+        .filter_map(|e| {
+            let path = e.path();
+            let modified = e.metadata().and_then(|m| m.modified()).ok();
+
+            if let Some((cached_at, manifest)) = previous.get(&path).filter(|(t, _)| Some(*t) == modified) {
+                current.insert(path, (*cached_at, manifest.clone()));
+                return Some(manifest.clone());
+            }
+
+            let document = fs::read_to_string(&path).ok()?;
+            match AppManifest::parse(&document) {
+                Ok(manifest) => {
+                    if let Some(modified) = modified {
+                        current.insert(path, (modified, manifest.clone()));
+                    }
+                    Some(manifest)
+                }
+                Err(err) => {
+                    debug!("Error parsing {path:?}: {err:?}");
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn manifest(state_flags: u64, bytes_to_download: u64, bytes_downloaded: u64) -> AppManifest {
+        AppManifest {
+            app_id: 570,
+            name: Some("Dota 2".to_owned()),
+            state_flags,
+            bytes_to_download,
+            bytes_downloaded,
+        }
+    }
+
+    #[test]
+    fn parses_app_manifest() -> Result<()> {
+        let parsed = AppManifest::parse(r#"
+            "AppState"
+            {
+                "appid"		"570"
+                "name"		"Dota 2"
+                "StateFlags"		"1026"
+                "BytesToDownload"		"4000"
+                "BytesDownloaded"		"1000"
+            }
+        "#)?;
+
+        assert_eq!(parsed, manifest(1026, 4000, 1000));
+
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_manifest_without_appid() {
+        assert!(AppManifest::parse(r#""AppState" { "name" "Dota 2" }"#).is_err());
+    }
+
+    #[test]
+    fn detects_updates() {
+        assert!(!manifest(4, 0, 0).is_updating());
+        assert!(manifest(1026, 4000, 1000).is_updating());
+        assert!(manifest(0x100004, 4000, 1000).is_updating());
+        assert!(!manifest(0x100204, 4000, 1000).is_updating());
+    }
+
+    #[test]
+    fn computes_download_progress() {
+        assert_eq!(manifest(1026, 0, 0).download_progress(), None);
+        assert_eq!(manifest(1026, 4000, 1000).download_progress(), Some(25));
+        assert_eq!(manifest(1026, 4000, 5000).download_progress(), Some(100));
+    }
+
+    #[test]
+    fn reuses_unchanged_manifests() -> Result<()> {
+        let folder = std::env::temp_dir().join(format!("{}-manifests-{}", crate::constants::APP_NAME, std::process::id()));
+        fs::create_dir_all(&folder)?;
+        let path = folder.join("appmanifest_570.acf");
+        fs::write(&path, r#""AppState" { "appid" "570" "name" "Dota 2" "StateFlags" "4" }"#)?;
+
+        let mut first = ManifestCache::new();
+        let read = read_manifests(&folder, &ManifestCache::new(), &mut first);
+        assert_eq!(read, vec![manifest(4, 0, 0)]);
+
+        // an unchanged file is not parsed again
+        let modified = first[&path].0;
+        let previous = ManifestCache::from([(path.clone(), (modified, manifest(1026, 4000, 1000)))]);
+        let read = read_manifests(&folder, &previous, &mut ManifestCache::new());
+
+        fs::remove_dir_all(&folder)?;
+        assert_eq!(read, vec![manifest(1026, 4000, 1000)]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn parses_library_folders() -> Result<()> {
+        let folders = parse_library_folders(r#"
+            "libraryfolders"
+            {
+                "0"
+                {
+                    "path"		"/home/user/.local/share/Steam"
+                    "apps" { "570" "123" }
+                }
+                "1"		"/mnt/games/SteamLibrary"
+                "contentstatsid"		"-123"
+            }
+        "#)?;
+
+        assert_eq!(folders, vec![PathBuf::from("/home/user/.local/share/Steam"), PathBuf::from("/mnt/games/SteamLibrary")]);
+
+        Ok(())
+    }
+}
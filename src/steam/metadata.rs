@@ -0,0 +1,417 @@
+//! Looks up the names and images of apps from a chain of providers.
+//!
+//! Providers are asked in the configured order, and each field is taken from the first provider that has it.
+//! Only the fields the caller needs are looked up, so looking up a name does not download any images.
+//! The resolved metadata records which provider supplied each field.
+
+use super::cache::DocumentCache;
+use super::index::{Extracted, Source};
+use super::steamgriddb::AssetKind;
+use super::{appinfo, library};
+use crate::config::{self, MetadataOverride, MetadataProviderKind};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use std::collections::HashMap;
+
+
+/// What a provider knows about an app. Fields it does not know are `None`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AppMetadata {
+    pub name: Option<String>,
+    /// A small, square image
+    pub icon_url: Option<String>,
+    /// A large image, like the store header
+    pub image_url: Option<String>,
+}
+
+impl From<MetadataOverride> for AppMetadata {
+    fn from(value: MetadataOverride) -> Self {
+        Self {
+            name: value.name,
+            icon_url: value.icon_url,
+            image_url: value.image_url,
+        }
+    }
+}
+
+/// A set of the fields of [AppMetadata]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fields {
+    pub name: bool,
+    pub icon_url: bool,
+    pub image_url: bool,
+}
+
+impl Fields {
+    pub const NAME: Fields = Fields { name: true, icon_url: false, image_url: false };
+    pub const ICON: Fields = Fields { name: false, icon_url: true, image_url: false };
+    pub const IMAGES: Fields = Fields { name: false, icon_url: true, image_url: true };
+    pub const ALL: Fields = Fields { name: true, icon_url: true, image_url: true };
+
+    fn is_empty(self) -> bool {
+        !(self.name || self.icon_url || self.image_url)
+    }
+
+    fn intersects(self, other: Fields) -> bool {
+        (self.name && other.name) || (self.icon_url && other.icon_url) || (self.image_url && other.image_url)
+    }
+}
+
+/// A source of app metadata
+#[async_trait]
+pub trait MetadataProvider: Send + Sync {
+    /// The name of the provider, used for provenance and in logs
+    fn name(&self) -> &'static str;
+
+    /// The fields the provider can know. It is not asked when the caller needs none of them.
+    fn fields(&self) -> Fields {
+        Fields::ALL
+    }
+
+    /// Looks up an app
+    async fn metadata(&self, app_id: u32) -> Result<AppMetadata>;
+}
+
+/// A field of the resolved metadata, with the provider that supplied it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sourced<T> {
+    pub value: T,
+    pub provider: &'static str,
+}
+
+/// The metadata of an app, merged from all providers
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ResolvedMetadata {
+    pub name: Option<Sourced<String>>,
+    pub icon_url: Option<Sourced<String>>,
+    pub image_url: Option<Sourced<String>>,
+    /// Providers that failed, with their errors
+    pub errors: Vec<(&'static str, String)>,
+}
+
+impl ResolvedMetadata {
+    /// Fills the fields that are still missing
+    fn merge(&mut self, provider: &'static str, metadata: AppMetadata) {
+        let sourced = |value| Some(Sourced { value, provider });
+
+        if self.name.is_none() {
+            self.name = metadata.name.and_then(sourced);
+        }
+        if self.icon_url.is_none() {
+            self.icon_url = metadata.icon_url.and_then(sourced);
+        }
+        if self.image_url.is_none() {
+            self.image_url = metadata.image_url.and_then(sourced);
+        }
+    }
+
+    /// The fields of `wanted` that are not known yet
+    fn missing(&self, wanted: Fields) -> Fields {
+        Fields {
+            name: wanted.name && self.name.is_none(),
+            icon_url: wanted.icon_url && self.icon_url.is_none(),
+            image_url: wanted.image_url && self.image_url.is_none(),
+        }
+    }
+}
+
+/// Asks providers in order until the wanted fields are known
+pub struct MetadataChain {
+    providers: Vec<Box<dyn MetadataProvider>>,
+}
+
+impl MetadataChain {
+    pub fn new(providers: Vec<Box<dyn MetadataProvider>>) -> Self {
+        Self { providers }
+    }
+
+    /// Creates the providers from the configuration.
+    ///
+    /// Providers that are not configured, like SteamGridDB without an API key, are left out.
+    pub fn from_config(config: &config::Metadata, cache: &'static DocumentCache) -> Self {
+        let providers = config
+            .providers
+            .iter()
+            .filter_map(|kind| -> Option<Box<dyn MetadataProvider>> {
+                match kind {
+                    MetadataProviderKind::Overrides => Some(Box::new(OverridesProvider { overrides: config.overrides.clone() })),
+                    MetadataProviderKind::Local => Some(Box::new(LocalProvider { language: cache.locale().language.clone() })),
+                    MetadataProviderKind::StoreApi => Some(Box::new(StoreProvider { cache, source: Source::StoreApi })),
+                    MetadataProviderKind::StorePage => Some(Box::new(StoreProvider { cache, source: Source::StorePage })),
+                    MetadataProviderKind::Steamgriddb => config
+                        .steamgriddb_api_key
+                        .as_ref()
+                        .map(|_| Box::new(SteamGridDbProvider { cache }) as Box<dyn MetadataProvider>),
+                }
+            })
+            .collect();
+
+        Self::new(providers)
+    }
+
+    /// Asks the providers about the `wanted` fields of an app and merges their answers field by field.
+    ///
+    /// Stops as soon as all wanted fields are known. Other fields are kept if a provider happened to know them.
+    pub async fn resolve(&self, app_id: u32, wanted: Fields) -> ResolvedMetadata {
+        let mut resolved = ResolvedMetadata::default();
+
+        for provider in &self.providers {
+            let missing = resolved.missing(wanted);
+
+            if missing.is_empty() {
+                break;
+            }
+            if !provider.fields().intersects(missing) {
+                continue;
+            }
+
+            match provider.metadata(app_id).await {
+                Ok(metadata) => resolved.merge(provider.name(), metadata),
+                Err(e) => resolved.errors.push((provider.name(), format!("{e:#}"))),
+            }
+        }
+
+        resolved
+    }
+}
+
+/// Metadata set in the configuration
+struct OverridesProvider {
+    overrides: HashMap<u32, MetadataOverride>,
+}
+
+#[async_trait]
+impl MetadataProvider for OverridesProvider {
+    fn name(&self) -> &'static str {
+        "overrides"
+    }
+
+    async fn metadata(&self, app_id: u32) -> Result<AppMetadata> {
+        Ok(self.overrides.get(&app_id).cloned().map(AppMetadata::from).unwrap_or_default())
+    }
+}
+
+/// The Steam client's app manifests and `appinfo.vdf`
+struct LocalProvider {
+    /// The store language, for the translated names in `appinfo.vdf`
+    language: Option<String>,
+}
+
+#[async_trait]
+impl MetadataProvider for LocalProvider {
+    fn name(&self) -> &'static str {
+        "local"
+    }
+
+    async fn metadata(&self, app_id: u32) -> Result<AppMetadata> {
+        // both files are read from disk, `appinfo.vdf` is only scanned the first time
+        let (manifest, app_info) =
+            tokio::task::spawn_blocking(move || (library::app_manifest(app_id), appinfo::find_app_info(app_id))).await?;
+        let localized_name = self
+            .language
+            .as_deref()
+            .and_then(|l| app_info.as_ref()?.localized_name(l))
+            .map(str::to_owned);
+
+        // manifests and untranslated names are in English
+        Ok(AppMetadata {
+            icon_url: app_info.as_ref().and_then(|i| i.icon_url()),
+            name: localized_name
+                .or(manifest.and_then(|m| m.name))
+                .or_else(|| app_info.and_then(|i| i.name)),
+            image_url: None,
+        })
+    }
+}
+
+/// The Steam Store, through the document cache
+struct StoreProvider {
+    cache: &'static DocumentCache,
+    source: Source,
+}
+
+#[async_trait]
+impl MetadataProvider for StoreProvider {
+    fn name(&self) -> &'static str {
+        match self.source {
+            Source::StoreApi => "store_api",
+            Source::StorePage => "store_page",
+            Source::SteamgriddbIcons | Source::SteamgriddbGrids => "steamgriddb",
+        }
+    }
+
+    async fn metadata(&self, app_id: u32) -> Result<AppMetadata> {
+        let fields = self.cache.get_fields(self.source, app_id).await?;
+
+        if fields == Extracted::default() {
+            return Err(anyhow!("The app is not on the store"));
+        }
+
+        Ok(AppMetadata {
+            name: fields.name,
+            icon_url: fields.icon_url,
+            image_url: fields.header_image,
+        })
+    }
+}
+
+/// Community artwork from SteamGridDB, cached like store documents
+struct SteamGridDbProvider {
+    cache: &'static DocumentCache,
+}
+
+#[async_trait]
+impl MetadataProvider for SteamGridDbProvider {
+    fn name(&self) -> &'static str {
+        "steamgriddb"
+    }
+
+    fn fields(&self) -> Fields {
+        Fields::IMAGES
+    }
+
+    async fn metadata(&self, app_id: u32) -> Result<AppMetadata> {
+        Ok(AppMetadata {
+            name: None,
+            icon_url: self.cache.get_artwork(AssetKind::Icons, app_id).await?,
+            image_url: self.cache.get_artwork(AssetKind::Grids, app_id).await?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    struct FakeProvider {
+        name: &'static str,
+        metadata: Option<AppMetadata>,
+        calls: Arc<AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl MetadataProvider for FakeProvider {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        async fn metadata(&self, _app_id: u32) -> Result<AppMetadata> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            self.metadata.clone().ok_or_else(|| anyhow!("{} is down", self.name))
+        }
+    }
+
+    fn fake(name: &'static str, metadata: Option<AppMetadata>) -> (Box<dyn MetadataProvider>, Arc<AtomicUsize>) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        (Box::new(FakeProvider { name, metadata, calls: Arc::clone(&calls) }), calls)
+    }
+
+    fn name(value: &str) -> AppMetadata {
+        AppMetadata { name: Some(value.to_owned()), ..Default::default() }
+    }
+
+    #[tokio::test]
+    async fn merges_fields_in_order() {
+        let (first, _) = fake("first", Some(name("Dota 2")));
+        let (second, _) = fake(
+            "second",
+            Some(AppMetadata {
+                name: Some("DOTA 2".to_owned()),
+                icon_url: Some("icon.jpg".to_owned()),
+                image_url: None,
+            }),
+        );
+        let (third, _) = fake("third", Some(AppMetadata { image_url: Some("header.jpg".to_owned()), ..Default::default() }));
+
+        let resolved = MetadataChain::new(vec![first, second, third]).resolve(570, Fields::ALL).await;
+
+        assert_eq!(resolved.name, Some(Sourced { value: "Dota 2".to_owned(), provider: "first" }));
+        assert_eq!(resolved.icon_url, Some(Sourced { value: "icon.jpg".to_owned(), provider: "second" }));
+        assert_eq!(resolved.image_url, Some(Sourced { value: "header.jpg".to_owned(), provider: "third" }));
+    }
+
+    #[tokio::test]
+    async fn continues_after_errors() {
+        let (broken, _) = fake("broken", None);
+        let (working, _) = fake("working", Some(name("Dota 2")));
+
+        let resolved = MetadataChain::new(vec![broken, working]).resolve(570, Fields::ALL).await;
+
+        assert_eq!(resolved.name.map(|n| n.provider), Some("working"));
+        assert_eq!(resolved.errors, vec![("broken", "broken is down".to_owned())]);
+    }
+
+    #[tokio::test]
+    async fn stops_when_complete() {
+        let (complete, _) = fake(
+            "complete",
+            Some(AppMetadata {
+                name: Some("Dota 2".to_owned()),
+                icon_url: Some("icon.jpg".to_owned()),
+                image_url: Some("header.jpg".to_owned()),
+            }),
+        );
+        let (unused, calls) = fake("unused", Some(name("DOTA 2")));
+
+        MetadataChain::new(vec![complete, unused]).resolve(570, Fields::ALL).await;
+
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn stops_when_wanted_fields_are_known() {
+        let (names, _) = fake("names", Some(name("Dota 2")));
+        let (images, calls) = fake("images", Some(AppMetadata { icon_url: Some("icon.jpg".to_owned()), ..Default::default() }));
+
+        let resolved = MetadataChain::new(vec![names, images]).resolve(570, Fields::NAME).await;
+
+        assert_eq!(resolved.name.map(|n| n.value).as_deref(), Some("Dota 2"));
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn skips_providers_without_wanted_fields() {
+        let (artwork, calls) = fake("artwork", Some(AppMetadata { icon_url: Some("icon.jpg".to_owned()), ..Default::default() }));
+        let artwork = Box::new(ArtworkOnly(artwork));
+
+        let resolved = MetadataChain::new(vec![artwork]).resolve(570, Fields::NAME).await;
+
+        assert_eq!(resolved.name, None);
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+    }
+
+    /// A provider that only knows images
+    struct ArtworkOnly(Box<dyn MetadataProvider>);
+
+    #[async_trait]
+    impl MetadataProvider for ArtworkOnly {
+        fn name(&self) -> &'static str {
+            self.0.name()
+        }
+
+        fn fields(&self) -> Fields {
+            Fields::IMAGES
+        }
+
+        async fn metadata(&self, app_id: u32) -> Result<AppMetadata> {
+            self.0.metadata(app_id).await
+        }
+    }
+
+    #[tokio::test]
+    async fn uses_overrides() -> Result<()> {
+        let provider = OverridesProvider {
+            overrides: HashMap::from([(570, MetadataOverride { name: Some("Dota".to_owned()), ..Default::default() })]),
+        };
+
+        assert_eq!(provider.metadata(570).await?, name("Dota"));
+        assert_eq!(provider.metadata(730).await?, AppMetadata::default());
+
+        Ok(())
+    }
+}
@@ -1,14 +1,33 @@
+mod agegate;
+mod appinfo;
 mod cache;
+mod cookies;
+mod http;
+mod inflight;
+mod index;
+mod metadata;
+mod prefetch;
+mod runtime;
+mod steamgriddb;
+mod store;
+mod vdf;
 
+pub mod library;
 pub mod scanner;
+pub mod streaming;
 
 use anyhow::{anyhow, Result};
-use constants::{APPID_ENV_KEY, NO_APPID, STEAM_GAME_PATH_FRAGMENT};
-use once_cell::sync::OnceCell;
+use constants::{APPID_CMD_KEY, APPID_ENV_KEY, NO_APPID, STEAM_GAME_PATH_FRAGMENT};
+use once_cell::sync::{Lazy, OnceCell};
+use std::{collections::HashSet, future::Future, sync::Mutex, time::Duration};
 use sysinfo::{Process};
+use tracing::{debug, warn};
 use self::cache::DocumentCache;
+use self::metadata::{Fields, MetadataChain};
 
-use super::constants;
+pub use self::prefetch::{PrefetchProgress, PrefetchSummary};
+
+use super::{config::Configuration, constants};
 
 /// Describes functionalities of a Steam Proton process
 trait SteamProcess {
@@ -22,43 +41,240 @@ impl SteamProcess for Process {
     fn steam_appid(&self) -> u32 {
         let appid_environ = self.environ()
             .iter().filter_map(|e| e.to_str())
-            .find(|&e| e.starts_with(APPID_ENV_KEY));
+            .find_map(|e| e.strip_prefix(APPID_ENV_KEY).and_then(|v| v.strip_prefix('=')));
 
-        match appid_environ {
-            Some(id) => id.split('=').last().unwrap().parse::<u32>().unwrap(),
-            None => NO_APPID,
-        }
+        // reaper gets the AppId on its command line, which also works for processes in a runtime container
+        let appid = appid_environ.or_else(|| self.cmd()
+            .iter().filter_map(|c| c.to_str())
+            .find_map(|c| c.strip_prefix(APPID_CMD_KEY)));
+
+        appid.and_then(|id| id.parse::<u32>().ok()).unwrap_or(NO_APPID)
     }
 
     fn steam_path(&self) -> Result<Option<String>> {
-        let filtered: Vec<&str> = self
-            .cmd()
-            .iter().filter_map(|e| e.to_str())
-            .filter_map(|c| match c.contains(STEAM_GAME_PATH_FRAGMENT) && c.ends_with(".exe") {
-                true => Some(c),
-                false => None,
-            })
-            .collect();
-
-        match filtered.len() {
-            0 => Ok(None),
-            1 => Ok(Some(filtered[0].to_owned())),
-            _ => Err(anyhow!("Found multiple possible paths for process '{:?}'", self.name()))
-        }
+        find_game_path(self.cmd().iter().filter_map(|c| c.to_str()))
+            .map_err(|e| anyhow!("{e} for process '{:?}'", self.name()))
+    }
+}
+
+/// Finds the path of the game executable in a command line.
+///
+/// Paths from inside a Steam Linux Runtime container are mapped back to their host path and
+/// the runtime and Proton executables are skipped. Proton games are recognised by their `.exe`.
+fn find_game_path<'a>(args: impl Iterator<Item = &'a str>) -> Result<Option<String>> {
+    let mut candidates: Vec<String> = args
+        .filter_map(runtime::to_host_path)
+        .filter(|c| c.contains(STEAM_GAME_PATH_FRAGMENT))
+        .filter(|c| !runtime::is_compat_tool_path(c, STEAM_GAME_PATH_FRAGMENT))
+        .map(|c| c.into_owned())
+        .collect();
+
+    if candidates.iter().any(|c| c.ends_with(".exe")) {
+        candidates.retain(|c| c.ends_with(".exe"));
+    }
+
+    // the same path can show up anywhere in the command line, e.g. once as host and once as container path
+    let mut seen = HashSet::new();
+    candidates.retain(|c| seen.insert(c.clone()));
+
+    match candidates.len() {
+        0 => Ok(None),
+        1 => Ok(candidates.pop()),
+        _ => Err(anyhow!("Found multiple possible paths"))
     }
 }
 
 static CACHE: OnceCell<DocumentCache> = OnceCell::new();
+static METADATA: OnceCell<MetadataChain> = OnceCell::new();
+/// Apps whose metadata could not be looked up, which has already been logged
+static REPORTED_APPS: Lazy<Mutex<HashSet<u32>>> = Lazy::new(|| Mutex::new(HashSet::new()));
+
+/// Checks the settings that are only used once the cache is set up or the first request is sent
+pub fn config_errors(config: &Configuration) -> Vec<String> {
+    let age_gate = agegate::AgeGateAnswer::from_config(&config.age_gate).err();
+    let store = store::StoreLocale::from_config(&config.store).err();
+
+    age_gate
+        .into_iter()
+        .chain(store)
+        .map(|e| format!("{e:#}"))
+        .chain(http::config_errors(&config.http))
+        .collect()
+}
+
+/// Sets up the document cache from the configuration.
+///
+/// Has to be called before any metadata is looked up, otherwise the default options are used.
+pub fn init_cache(config: &Configuration) -> Result<()> {
+    let mut builder = cache::DocumentCacheBuilder::new()
+        .with_ttl(Duration::from_secs(config.cache.ttl_hours * 60 * 60))
+        .with_max_entries(config.cache.max_entries)
+        .with_max_size(config.cache.max_size_kb * 1024);
+
+    if config.cache_location.is_some() {
+        warn!("cache_location is deprecated, use cache.location instead");
+    }
+
+    if let Some(location) = config.cache_location() {
+        builder = builder.with_location(location);
+    }
+
+    builder = builder
+        .with_locale(store::StoreLocale::from_config(&config.store)?)
+        .with_age_gate(agegate::AgeGateAnswer::from_config(&config.age_gate)?)
+        .with_steamgriddb_api_key(config.metadata.steamgriddb_api_key.clone())
+        .with_http(config.http.clone());
+
+    let cache = builder.build()?;
+
+    CACHE.set(cache).map_err(|_| anyhow!("The document cache was already set up"))
+}
 
 fn get_cache() -> &'static DocumentCache {
     CACHE.get_or_init(|| cache::DocumentCacheBuilder::new().build().expect("Error creating the document cache"))
 }
 
-#[derive(Debug, PartialEq, Eq)]
+/// Writes the changes to the metadata index to disk, if it was not written within the last minute
+pub async fn flush_cache() {
+    if let Some(cache) = CACHE.get() {
+        cache.flush_index().await;
+    }
+}
+
+/// Writes state that is kept between restarts, like the cookie jar and the metadata index, to disk
+pub fn save_state() {
+    if let Some(cache) = CACHE.get() {
+        cache.save_cookies();
+        cache.save_index_blocking();
+    }
+}
+
+/// Sets up the metadata providers from the configuration.
+///
+/// Has to be called after [init_cache] and before any metadata is looked up, otherwise the default providers are used.
+pub fn init_metadata(config: &Configuration) -> Result<()> {
+    let chain = MetadataChain::from_config(&config.metadata, get_cache());
+
+    METADATA.set(chain).map_err(|_| anyhow!("The metadata providers were already set up"))
+}
+
+fn get_metadata() -> &'static MetadataChain {
+    METADATA.get_or_init(|| MetadataChain::from_config(&Default::default(), get_cache()))
+}
+
+/// Warms the metadata cache for every app installed in the Steam libraries.
+///
+/// Waits for `delay` after every app that had to be downloaded, see [prefetch::prefetch].
+pub async fn prefetch_library(delay: Duration, progress: impl FnMut(&PrefetchProgress)) -> PrefetchSummary {
+    let manifests = tokio::task::spawn_blocking(library::app_manifests).await.unwrap_or_default();
+    let mut app_ids: Vec<u32> = manifests.iter().map(|m| m.app_id).collect();
+    app_ids.sort_unstable();
+    app_ids.dedup();
+
+    let cache = get_cache();
+
+    prefetch::prefetch(&app_ids, get_metadata(), |app_id| cache.is_fresh(app_id), |app_id| cache.refresh(app_id), delay, progress).await
+}
+
+/// Gets the steam url to an app's store page
+pub fn get_steam_url(app_id: u32) -> String {
+    format!("https://store.steampowered.com/app/{app_id}/")
+}
+
+/// Days between 1970-01-01 and a date of the proleptic Gregorian calendar, `month` being 1 to 12
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    // the year is counted from March, so the leap day is at its end
+    let (year, month) = match month {
+        1 | 2 => (year - 1, month + 9),
+        _ => (year, month - 3),
+    };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let day_of_year = (153 * month + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146097 + day_of_era - 719468
+}
+
+/// How long a name from the providers that do not ask the store is used before it is looked up again
+const STAND_IN_NAME_TTL: Duration = Duration::from_secs(15 * 60);
+/// Providers whose names are only used when the store could not be asked, or does not know the app
+const STAND_IN_PROVIDERS: [&str; 2] = ["local", "fallback"];
+/// How long a name is looked up before the stand-in name is shown, including all retries
+const NAME_LOOKUP_TIMEOUT: Duration = Duration::from_secs(20);
+/// When to look up a name again whose lookup took too long. It goes on in the background and is cached by then.
+const SLOW_NAME_RETRY: Duration = Duration::from_secs(60);
+
+/// The resolved name of an app
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AppName {
+    pub value: String,
+    /// For stand-in names, when the name should be looked up again
+    pub retry_after: Option<Duration>,
+}
+
+/// Resolves an app's name with the configured metadata providers.
+///
+/// Apps that none of the providers know, e.g. because they are not on the store and not installed,
+/// are called `Steam App <id>`. That name and those from the Steam client's files are only stand-ins,
+/// which should be looked up again after [AppName::retry_after].
+///
+/// Lookups that take longer than [NAME_LOOKUP_TIMEOUT] go on in the background, and `Steam App <id>` is used until
+/// the name is looked up again after [SLOW_NAME_RETRY].
+pub async fn get_app_name(app_id: u32) -> AppName {
+    let Some(resolved) = resolve_within(get_metadata().resolve(app_id, Fields::NAME), NAME_LOOKUP_TIMEOUT).await else {
+        let name = fallback_app_name(app_id);
+        warn!(%app_id, "Looking up the name took longer than {NAME_LOOKUP_TIMEOUT:?}, using {name:?} for now");
+
+        return AppName { value: name, retry_after: Some(SLOW_NAME_RETRY) };
+    };
+
+    let (name, provider) = match resolved.name {
+        Some(name) => (name.value, name.provider),
+        None => (fallback_app_name(app_id), "fallback"),
+    };
+
+    // the lookup is repeated every time the activity changes, so only warn about it once
+    match !resolved.errors.is_empty() && REPORTED_APPS.lock().unwrap().insert(app_id) {
+        true => warn!(%app_id, provider, errors = ?resolved.errors, "Could not look up all metadata, using {name:?}"),
+        false => debug!(%app_id, provider, "Resolved name {name:?}"),
+    }
+
+    AppName {
+        value: name,
+        retry_after: name_retry_after(provider),
+    }
+}
+
+/// Runs a lookup as its own task and waits for it for at most `timeout`. The lookup is not cancelled when it takes longer.
+async fn resolve_within<T: Send + 'static>(lookup: impl Future<Output = T> + Send + 'static, timeout: Duration) -> Option<T> {
+    let task = tokio::spawn(lookup);
+
+    match tokio::time::timeout(timeout, task).await {
+        Ok(Ok(resolved)) => Some(resolved),
+        Ok(Err(e)) => {
+            warn!("Name lookup failed: {e}");
+            None
+        }
+        Err(_) => None,
+    }
+}
+
+fn name_retry_after(provider: &str) -> Option<Duration> {
+    STAND_IN_PROVIDERS.contains(&provider).then_some(STAND_IN_NAME_TTL)
+}
+
+fn fallback_app_name(app_id: u32) -> String {
+    format!("Steam App {app_id}")
+}
+
+#[derive(Debug, PartialEq)]
 pub struct SteamApp {
     pub app_id: u32,
     pub path: String,
     pub running_since: i64,
+    /// CPU usage of the game's processes since the previous scan, in percent of one core
+    pub cpu_usage: f32,
 }
 
 impl SteamApp {
@@ -71,30 +287,36 @@ impl SteamApp {
         )
     }
 
+    #[allow(dead_code)]
     /// Gets the steam url to the games' store page
     pub fn get_steam_url(&self) -> String {
-        format!("https://store.steampowered.com/app/{}/", self.app_id)
+        get_steam_url(self.app_id)
     }
 
-    /// Try to resolve the game's name by scraping the store page
-    pub async fn get_name(&self) -> Result<String> {
-        let steam_url = self.get_steam_url();
-        get_cache().get_name(steam_url.as_str()).await
+    #[allow(dead_code)]
+    /// Resolves the game's name, see [get_app_name]
+    pub async fn get_name(&self) -> String {
+        get_app_name(self.app_id).await.value
     }
 
     #[allow(dead_code)]
     /// Gets the url to the game's icon
     pub async fn get_app_icon_url(&self) -> Result<String> {
-        let steam_url = self.get_steam_url();
-        get_cache().get_appicon(steam_url.as_str()).await
+        get_metadata()
+            .resolve(self.app_id, Fields::ICON)
+            .await
+            .icon_url
+            .map(|icon| icon.value)
+            .ok_or_else(|| anyhow!("None of the metadata providers has an icon for app {}", self.app_id))
     }
 }
 
 #[cfg(test)]
 mod tests {
 
-    use super::SteamApp;
+    use super::{days_from_civil, fallback_app_name, find_game_path, name_retry_after, resolve_within, SteamApp, STAND_IN_NAME_TTL};
     use anyhow::Result;
+    use std::time::Duration;
 
     #[test]
     fn steamapp_renders_store_url() -> Result<()> {
@@ -102,6 +324,7 @@ mod tests {
             app_id: 1,
             path: String::from(""),
             running_since: 18,
+            cpu_usage: 0.0,
         };
 
         let store_url = app.get_steam_url();
@@ -110,4 +333,104 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn counts_days_since_epoch() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(1969, 12, 31), -1);
+        assert_eq!(days_from_civil(2000, 3, 1), 11017);
+        assert_eq!(days_from_civil(2026, 10, 18), 20744);
+    }
+
+    #[test]
+    fn falls_back_to_generic_name() {
+        assert_eq!(fallback_app_name(1091500), "Steam App 1091500");
+    }
+
+    #[test]
+    fn looks_up_stand_in_names_again() {
+        assert_eq!(name_retry_after("fallback"), Some(STAND_IN_NAME_TTL));
+        assert_eq!(name_retry_after("local"), Some(STAND_IN_NAME_TTL));
+        assert_eq!(name_retry_after("store_api"), None);
+        assert_eq!(name_retry_after("overrides"), None);
+    }
+
+    #[tokio::test]
+    async fn gives_up_on_slow_lookups() {
+        let slow = async {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            "Dota 2"
+        };
+
+        assert_eq!(resolve_within(async { "Dota 2" }, Duration::from_secs(1)).await, Some("Dota 2"));
+        assert_eq!(resolve_within(slow, Duration::from_millis(10)).await, None);
+    }
+
+    #[test]
+    fn finds_proton_game_path() -> Result<()> {
+        let cmd = [
+            "/home/user/.steam/steam/ubuntu12_32/reaper",
+            "SteamLaunch",
+            "AppId=1091500",
+            "--",
+            "/home/user/.steam/steam/steamapps/common/SteamLinuxRuntime_sniper/_v2-entry-point",
+            "--verb=waitforexitandrun",
+            "--",
+            "/home/user/.steam/steam/steamapps/common/Proton 9.0 (Beta)/proton",
+            "waitforexitandrun",
+            "/home/user/.steam/steam/steamapps/common/Cyberpunk 2077/bin/x64/Cyberpunk2077.exe",
+        ];
+
+        let path = find_game_path(cmd.into_iter())?;
+
+        assert_eq!(path.as_deref(), Some("/home/user/.steam/steam/steamapps/common/Cyberpunk 2077/bin/x64/Cyberpunk2077.exe"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn finds_native_game_path_in_runtime() -> Result<()> {
+        let cmd = [
+            "/run/host/home/user/.steam/steam/steamapps/common/SteamLinuxRuntime_sniper/_v2-entry-point",
+            "--verb=waitforexitandrun",
+            "--",
+            "/run/host/mnt/games/SteamLibrary/steamapps/common/Portal 2/portal2.sh",
+            "-game",
+            "portal2",
+        ];
+
+        let path = find_game_path(cmd.into_iter())?;
+
+        assert_eq!(path.as_deref(), Some("/mnt/games/SteamLibrary/steamapps/common/Portal 2/portal2.sh"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn removes_repeated_game_paths() -> Result<()> {
+        let cmd = [
+            "/mnt/games/SteamLibrary/steamapps/common/Portal 2/portal2.sh",
+            "-game",
+            "portal2",
+            "/run/host/mnt/games/SteamLibrary/steamapps/common/Portal 2/portal2.sh",
+        ];
+
+        let path = find_game_path(cmd.into_iter())?;
+
+        assert_eq!(path.as_deref(), Some("/mnt/games/SteamLibrary/steamapps/common/Portal 2/portal2.sh"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn ignores_runtime_only_command_lines() -> Result<()> {
+        let cmd = [
+            "/run/pressure-vessel/pv-from-host/bin/pv-adverb",
+            "/home/user/.steam/steam/steamapps/common/SteamLinuxRuntime_soldier/_v2-entry-point",
+        ];
+
+        assert!(find_game_path(cmd.into_iter())?.is_none());
+
+        Ok(())
+    }
 }
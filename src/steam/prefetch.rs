@@ -0,0 +1,128 @@
+//! Warming the metadata cache for the installed library, so games show up right away on their first launch.
+
+use super::metadata::{Fields, MetadataChain};
+use std::{future::Future, time::Duration};
+use tracing::debug;
+
+/// The state of a prefetch after an app was looked up
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrefetchProgress {
+    /// Apps looked up so far, including this one
+    pub done: usize,
+    pub total: usize,
+    pub app_id: u32,
+    /// The resolved name, `None` if no provider knows the app
+    pub name: Option<String>,
+}
+
+/// What a prefetch achieved
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PrefetchSummary {
+    pub total: usize,
+    /// Apps that were not cached, or only with stale metadata
+    pub fetched: usize,
+    /// Apps none of the providers know
+    pub unknown: usize,
+}
+
+/// Looks up the name of every app with the metadata providers, which caches what they download.
+///
+/// Apps with fresh metadata are only read from the cache, stale metadata is downloaded again with `refresh` first.
+/// After each app that needed a download, the prefetch waits for `delay`, to leave the rate limit to lookups of running games.
+pub async fn prefetch<F: Future<Output = ()>>(
+    app_ids: &[u32],
+    chain: &MetadataChain,
+    is_fresh: impl Fn(u32) -> bool,
+    refresh: impl Fn(u32) -> F,
+    delay: Duration,
+    mut progress: impl FnMut(&PrefetchProgress),
+) -> PrefetchSummary {
+    let mut summary = PrefetchSummary { total: app_ids.len(), ..Default::default() };
+
+    for (i, &app_id) in app_ids.iter().enumerate() {
+        let fresh = is_fresh(app_id);
+        if !fresh {
+            refresh(app_id).await;
+        }
+
+        // the names are what running games are looked up for first
+        let resolved = chain.resolve(app_id, Fields::NAME).await;
+
+        if !resolved.errors.is_empty() {
+            debug!(%app_id, errors = ?resolved.errors, "Some metadata providers failed during prefetch");
+        }
+
+        let name = resolved.name.map(|n| n.value);
+        if name.is_none() {
+            summary.unknown += 1;
+        }
+
+        progress(&PrefetchProgress { done: i + 1, total: app_ids.len(), app_id, name });
+
+        if !fresh {
+            summary.fetched += 1;
+
+            if !delay.is_zero() && i + 1 < app_ids.len() {
+                tokio::time::sleep(delay).await;
+            }
+        }
+    }
+
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::steam::metadata::{AppMetadata, MetadataProvider};
+    use anyhow::{anyhow, Result};
+    use async_trait::async_trait;
+
+    struct KnowsDota;
+
+    #[async_trait]
+    impl MetadataProvider for KnowsDota {
+        fn name(&self) -> &'static str {
+            "dota"
+        }
+
+        async fn metadata(&self, app_id: u32) -> Result<AppMetadata> {
+            match app_id {
+                570 => Ok(AppMetadata { name: Some("Dota 2".to_owned()), ..Default::default() }),
+                _ => Err(anyhow!("Unknown app")),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn reports_progress() {
+        let chain = MetadataChain::new(vec![Box::new(KnowsDota)]);
+        let mut reported = Vec::new();
+        let refreshed = std::sync::Mutex::new(Vec::new());
+
+        let summary = prefetch(
+            &[570, 730],
+            &chain,
+            |app_id| app_id == 570,
+            |app_id| {
+                refreshed.lock().unwrap().push(app_id);
+                async {}
+            },
+            Duration::ZERO,
+            |p| reported.push(p.clone()),
+        )
+        .await;
+
+        assert_eq!(summary, PrefetchSummary { total: 2, fetched: 1, unknown: 1 });
+        // only stale apps are downloaded again
+        assert_eq!(refreshed.into_inner().unwrap(), vec![730]);
+        assert_eq!(
+            reported,
+            vec![
+                PrefetchProgress { done: 1, total: 2, app_id: 570, name: Some("Dota 2".to_owned()) },
+                PrefetchProgress { done: 2, total: 2, app_id: 730, name: None },
+            ]
+        );
+    }
+}
@@ -0,0 +1,134 @@
+//! Support for games running inside the Steam Linux Runtime (pressure-vessel) containers.
+//!
+//! Inside the container the host file system is mounted under `/run/host` and the runtime keeps
+//! its own files under `/run/pressure-vessel`, so paths we read from a process' command line need
+//! to be translated before we can recognise them as games.
+
+use std::borrow::Cow;
+
+/// Mount point of the host's file system inside a pressure-vessel container
+const HOST_MOUNT_PREFIX: &str = "/run/host";
+/// Location of pressure-vessel's own files inside the container. These have no host counterpart.
+const PRESSURE_VESSEL_PREFIX: &str = "/run/pressure-vessel";
+
+/// Names of the processes the Steam Linux Runtime puts between Steam and the game
+const WRAPPER_PROCESS_NAMES: [&str; 8] = [
+    "pressure-vessel-wrap",
+    "pressure-vessel-adverb",
+    "pv-adverb",
+    "pv-bwrap",
+    "srt-bwrap",
+    "steam-runtime-launcher-service",
+    "steam-launch-wrapper",
+    "_v2-entry-point",
+];
+
+/// Name of the service that launches processes inside an already running runtime container
+pub const LAUNCHER_SERVICE_NAME: &str = "steam-runtime-launcher-service";
+
+/// Directories in `steamapps/common` that contain compatibility tools instead of games
+const COMPAT_TOOL_DIR_PREFIXES: [&str; 3] = ["SteamLinuxRuntime", "Proton", "Steamworks Shared"];
+
+/// Maps a path as seen from inside a runtime container back to the path on the host.
+///
+/// Returns `None` for paths that only exist inside the container.
+pub fn to_host_path(path: &str) -> Option<Cow<'_, str>> {
+    if is_below(path, PRESSURE_VESSEL_PREFIX) {
+        return None;
+    }
+
+    if is_below(path, HOST_MOUNT_PREFIX) {
+        let host_path = &path[HOST_MOUNT_PREFIX.len()..];
+        return match host_path.is_empty() {
+            true => Some(Cow::Borrowed("/")),
+            false => Some(Cow::Borrowed(host_path)),
+        };
+    }
+
+    Some(Cow::Borrowed(path))
+}
+
+/// Returns true if the process name belongs to one of the runtime's wrapper processes
+pub fn is_wrapper_process(name: &str) -> bool {
+    WRAPPER_PROCESS_NAMES.iter().any(|w| name.eq_ignore_ascii_case(w))
+}
+
+/// Returns true if the path points into a compatibility tool (Proton, a runtime, ...) that Steam
+/// installs next to the games.
+///
+/// * `path`: a host path
+/// * `library_fragment`: the path fragment that marks the start of the game directories
+pub fn is_compat_tool_path(path: &str, library_fragment: &str) -> bool {
+    let Some(index) = path.find(library_fragment) else {
+        return false;
+    };
+
+    let tool_dir = path[index + library_fragment.len()..].trim_start_matches('/');
+
+    COMPAT_TOOL_DIR_PREFIXES.iter().any(|p| tool_dir.starts_with(p))
+        || tool_dir
+            .rsplit('/')
+            .next()
+            .is_some_and(is_wrapper_process)
+}
+
+/// Checks if `path` is `prefix` or a path below it
+fn is_below(path: &str, prefix: &str) -> bool {
+    path.strip_prefix(prefix)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    const FRAGMENT: &str = "steamapps/common";
+
+    #[test]
+    fn maps_host_mount_to_host_path() {
+        let path = to_host_path("/run/host/home/user/.steam/steam/steamapps/common/Game/game");
+
+        assert_eq!(path.as_deref(), Some("/home/user/.steam/steam/steamapps/common/Game/game"));
+    }
+
+    #[test]
+    fn keeps_shared_paths() {
+        let path = to_host_path("/home/user/.steam/steam/steamapps/common/Game/game");
+
+        assert_eq!(path.as_deref(), Some("/home/user/.steam/steam/steamapps/common/Game/game"));
+    }
+
+    #[test]
+    fn ignores_similar_prefixes() {
+        assert_eq!(to_host_path("/run/hostess/game").as_deref(), Some("/run/hostess/game"));
+    }
+
+    #[test]
+    fn drops_container_only_paths() {
+        assert!(to_host_path("/run/pressure-vessel/pv-from-host/bin/pv-adverb").is_none());
+    }
+
+    #[test]
+    fn recognises_wrapper_processes() {
+        assert!(is_wrapper_process("pv-adverb"));
+        assert!(is_wrapper_process("steam-runtime-launcher-service"));
+        assert!(!is_wrapper_process("game.x86_64"));
+    }
+
+    #[test]
+    fn recognises_compat_tools() {
+        assert!(is_compat_tool_path(
+            "/home/user/.steam/steam/steamapps/common/SteamLinuxRuntime_sniper/_v2-entry-point",
+            FRAGMENT
+        ));
+        assert!(is_compat_tool_path(
+            "/home/user/.steam/steam/steamapps/common/Proton 9.0 (Beta)/proton",
+            FRAGMENT
+        ));
+        assert!(!is_compat_tool_path(
+            "/home/user/.steam/steam/steamapps/common/Portal 2/portal2.sh",
+            FRAGMENT
+        ));
+    }
+}
@@ -1,13 +1,33 @@
 use super::{*};
-use sysinfo::{Process, ProcessesToUpdate, RefreshKind, System};
-use anyhow::Result;
+use constants::STEAM_SHADER_CACHE_PATH_FRAGMENT;
+use std::collections::{HashMap, HashSet};
+use sysinfo::{Pid, Process, ProcessesToUpdate, RefreshKind, System};
 
-/// Returns true if the process was started with wine64-preloader
-fn filter_process(proc: &Process) -> bool {
+/// Process name of Steam's shader pre-caching tool. The kernel truncates process names to 15 characters.
+const FOSSILIZE_PROCESS_PREFIX: &str = "fossilize_repla";
+/// Process name of the Steam client
+const STEAM_CLIENT_PROCESS_NAME: &str = "steam";
+/// Steam client arguments that start it in Big Picture mode
+const BIG_PICTURE_ARGS: [&str; 4] = ["-gamepadui", "-bigpicture", "-steamdeck", "-steamos3"];
+/// Process name of the gamescope session, which runs Steam in Big Picture mode
+const GAMESCOPE_SESSION_PREFIX: &str = "gamescope-sessi";
+/// Process names of the VR runtimes, truncated to 15 characters like the kernel does
+const VR_RUNTIME_PROCESSES: [(&str, VrRuntime); 4] = [
+    ("vrserver", VrRuntime::SteamVr),
+    ("vrmonitor", VrRuntime::SteamVr),
+    ("monado-service", VrRuntime::Monado),
+    ("wivrn-server", VrRuntime::WiVRn),
+];
+
+/// Returns true if the process was started by Steam's reaper, or inside a runtime container by the launcher service
+fn filter_process(proc: &Process, sys: &System) -> bool {
     proc.name().eq_ignore_ascii_case("reaper")
+        || proc.parent()
+            .and_then(|pid| sys.process(pid))
+            .is_some_and(|parent| parent.name().eq_ignore_ascii_case(runtime::LAUNCHER_SERVICE_NAME))
 }
 
-fn process_to_steamapp(steamproc: &Process) -> Option<SteamApp> {
+fn process_to_steamapp(steamproc: &Process, cpu_usage: f32) -> Option<SteamApp> {
     let path = steamproc.steam_path()
         .unwrap_or(None);
 
@@ -17,21 +37,241 @@ fn process_to_steamapp(steamproc: &Process) -> Option<SteamApp> {
         app_id: steamproc.steam_appid(),
         path: path.unwrap(),
         running_since: steamproc.start_time() as i64,
+        cpu_usage,
     })
 }
 
-/// Gets all running steam games
-pub fn get_running_steam_games() -> Result<Vec<SteamApp>, &'static str> {
-    let mut sys = System::new_with_specifics(RefreshKind::everything());
+/// Finds the AppId of the shader cache a `fossilize_replay` command line works on
+fn shader_cache_appid<'a>(mut args: impl Iterator<Item = &'a str>) -> Option<u32> {
+    args.find_map(|a| {
+        let index = a.find(STEAM_SHADER_CACHE_PATH_FRAGMENT)?;
+        a[index + STEAM_SHADER_CACHE_PATH_FRAGMENT.len()..]
+            .split('/')
+            .next()?
+            .parse::<u32>()
+            .ok()
+    })
+}
 
-    sys.refresh_processes(ProcessesToUpdate::All, true);
+/// Steam compiling the shaders of an app before it starts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ShaderCompilation {
+    pub app_id: u32,
+    pub running_since: i64,
+}
 
-    let apps: Vec<SteamApp> = sys
-        .processes()
+/// The running Steam client
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SteamClient {
+    pub running_since: i64,
+    /// Steam runs in Big Picture mode, or as a gamescope session
+    pub big_picture: bool,
+}
+
+/// The VR runtimes we can detect
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VrRuntime {
+    SteamVr,
+    Monado,
+    WiVRn,
+}
+
+/// A running VR session
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VrSession {
+    pub runtime: VrRuntime,
+    pub running_since: i64,
+}
+
+/// Finds the VR runtime a process belongs to
+fn vr_runtime(process_name: &str) -> Option<VrRuntime> {
+    VR_RUNTIME_PROCESSES
         .iter()
-        .filter(|(_, p)| filter_process(p))
-        .map_while(|(_, p)| process_to_steamapp(p))
-        .collect();
+        .find(|(name, _)| process_name.eq_ignore_ascii_case(name))
+        .map(|(_, runtime)| *runtime)
+}
+
+/// Returns true if the Steam client was started in Big Picture mode
+fn is_big_picture<'a>(mut args: impl Iterator<Item = &'a str>) -> bool {
+    args.any(|a| BIG_PICTURE_ARGS.iter().any(|b| a.eq_ignore_ascii_case(b)))
+}
+
+/// The result of scanning the running processes
+#[derive(Debug, Default)]
+pub struct Scan {
+    pub games: Vec<SteamApp>,
+    pub shader_compilations: Vec<ShaderCompilation>,
+    pub steam_client: Option<SteamClient>,
+    pub vr_session: Option<VrSession>,
+}
+
+/// Scans the running processes for Steam games.
+///
+/// The scanner keeps its process list between scans, so CPU usage is measured over the time between two scans.
+pub struct Scanner {
+    sys: System,
+}
+
+impl Scanner {
+    /// Creates a new scanner
+    pub fn new() -> Self {
+        Self { sys: System::new_with_specifics(RefreshKind::everything()) }
+    }
+
+    /// Scans the running processes
+    pub fn scan(&mut self) -> Scan {
+        self.sys.refresh_processes(ProcessesToUpdate::All, true);
+
+        Scan {
+            games: self.get_running_steam_games(),
+            shader_compilations: self.get_shader_compilations(),
+            steam_client: self.get_steam_client(),
+            vr_session: self.get_vr_session(),
+        }
+    }
+
+    /// Gets all running steam games
+    fn get_running_steam_games(&self) -> Vec<SteamApp> {
+        let children = self.children_by_parent();
+
+        let mut apps: Vec<SteamApp> = self.processes()
+            .filter(|p| filter_process(p, &self.sys))
+            .filter_map(|p| process_to_steamapp(p, self.tree_cpu_usage(p.pid(), &children)))
+            .collect();
+
+        // a game inside a runtime container can be seen through both reaper and the launcher service
+        apps.sort_by_key(|a| a.running_since);
+        let mut seen = HashSet::new();
+        apps.retain(|a| a.app_id == NO_APPID || seen.insert(a.app_id));
+
+        apps
+    }
+
+    /// Finds the Steam client
+    fn get_steam_client(&self) -> Option<SteamClient> {
+        let client = self.processes()
+            .filter(|p| p.name().eq_ignore_ascii_case(STEAM_CLIENT_PROCESS_NAME))
+            .min_by_key(|p| p.start_time())?;
+
+        let gamescope_session = self.processes()
+            .any(|p| p.name().to_string_lossy().starts_with(GAMESCOPE_SESSION_PREFIX));
+
+        Some(SteamClient {
+            running_since: client.start_time() as i64,
+            big_picture: gamescope_session || is_big_picture(client.cmd().iter().filter_map(|c| c.to_str())),
+        })
+    }
+
+    /// Finds a running VR runtime
+    fn get_vr_session(&self) -> Option<VrSession> {
+        self.processes()
+            .filter_map(|p| {
+                vr_runtime(&p.name().to_string_lossy())
+                    .map(|runtime| VrSession { runtime, running_since: p.start_time() as i64 })
+            })
+            .min_by_key(|s| s.running_since)
+    }
+
+    /// All processes, without their threads
+    fn processes(&self) -> impl Iterator<Item = &Process> {
+        self.sys.processes().values().filter(|p| p.thread_kind().is_none())
+    }
+
+    /// Gets the apps Steam is pre-compiling shaders for
+    fn get_shader_compilations(&self) -> Vec<ShaderCompilation> {
+        let mut compilations: Vec<ShaderCompilation> = self.processes()
+            .filter(|p| p.name().to_string_lossy().starts_with(FOSSILIZE_PROCESS_PREFIX))
+            .filter_map(|p| {
+                shader_cache_appid(p.cmd().iter().filter_map(|c| c.to_str()))
+                    .map(|app_id| ShaderCompilation { app_id, running_since: p.start_time() as i64 })
+            })
+            .collect();
+
+        // fossilize runs several worker processes per app
+        compilations.sort_by_key(|c| c.running_since);
+        let mut seen = HashSet::new();
+        compilations.retain(|c| seen.insert(c.app_id));
+
+        compilations
+    }
+
+    fn children_by_parent(&self) -> HashMap<Pid, Vec<Pid>> {
+        let mut children: HashMap<Pid, Vec<Pid>> = HashMap::new();
+
+        // threads are listed as processes too, but their CPU usage is already part of their process
+        for proc in self.processes() {
+            if let Some(parent) = proc.parent() {
+                children.entry(parent).or_default().push(proc.pid());
+            }
+        }
+
+        children
+    }
+
+    /// Sums the CPU usage of a process and all of its descendants.
+    ///
+    /// The game itself is a descendant of the process we detect it through.
+    fn tree_cpu_usage(&self, root: Pid, children: &HashMap<Pid, Vec<Pid>>) -> f32 {
+        let mut usage = 0.0;
+        let mut pending = vec![root];
+
+        while let Some(pid) = pending.pop() {
+            if let Some(proc) = self.sys.process(pid) {
+                usage += proc.cpu_usage();
+            }
+            if let Some(kids) = children.get(&pid) {
+                pending.extend(kids);
+            }
+        }
+
+        usage
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::{is_big_picture, shader_cache_appid, vr_runtime, VrRuntime};
+
+    #[test]
+    fn finds_shader_cache_appid() {
+        let cmd = [
+            "/home/user/.steam/steam/ubuntu12_64/fossilize_replay",
+            "--master-process",
+            "--num-threads",
+            "4",
+            "/home/user/.steam/steam/steamapps/shadercache/1091500/fozpipelinesv6/steamapprun_pipeline_cache.foz",
+        ];
+
+        assert_eq!(shader_cache_appid(cmd.into_iter()), Some(1091500));
+    }
+
+    #[test]
+    fn ignores_other_arguments() {
+        let cmd = ["/home/user/.steam/steam/ubuntu12_64/fossilize_replay", "--slave-process", "--shmem-fd", "42"];
+
+        assert_eq!(shader_cache_appid(cmd.into_iter()), None);
+    }
+
+    #[test]
+    fn detects_big_picture_mode() {
+        let cmd = ["/home/user/.steam/steam/ubuntu12_32/steam", "-gamepadui", "-steamos3"];
+
+        assert!(is_big_picture(cmd.into_iter()));
+    }
+
+    #[test]
+    fn detects_desktop_mode() {
+        let cmd = ["/home/user/.steam/steam/ubuntu12_32/steam", "-silent"];
+
+        assert!(!is_big_picture(cmd.into_iter()));
+    }
 
-    Ok(apps)
+    #[test]
+    fn detects_vr_runtimes() {
+        assert_eq!(vr_runtime("vrserver"), Some(VrRuntime::SteamVr));
+        assert_eq!(vr_runtime("monado-service"), Some(VrRuntime::Monado));
+        assert_eq!(vr_runtime("wivrn-server"), Some(VrRuntime::WiVRn));
+        assert_eq!(vr_runtime("steam"), None);
+    }
 }
\ No newline at end of file
@@ -0,0 +1,70 @@
+//! Client types for the SteamGridDB API, which has community artwork for Steam apps.
+
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+
+const STEAMGRIDDB_BASE_URL: &str = "https://www.steamgriddb.com/api/v2";
+
+/// The kinds of artwork we use
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AssetKind {
+    /// Small, square icons
+    Icons,
+    /// Large images, like the store header
+    Grids,
+}
+
+/// The response of the asset endpoints
+#[derive(Deserialize, Debug)]
+struct AssetsResponse {
+    success: bool,
+    #[serde(default)]
+    data: Vec<Asset>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Asset {
+    url: String,
+}
+
+/// Gets the url of the assets of a Steam app
+pub fn get_assets_url(kind: AssetKind, app_id: u32) -> String {
+    let kind = match kind {
+        AssetKind::Icons => "icons",
+        AssetKind::Grids => "grids",
+    };
+
+    format!("{STEAMGRIDDB_BASE_URL}/{kind}/steam/{app_id}")
+}
+
+/// Gets the url of the first asset in a response. `Ok(None)` if SteamGridDB knows the app, but has no such assets.
+pub fn parse_assets(body: &str) -> Result<Option<String>> {
+    let response: AssetsResponse = serde_json::from_str(body)?;
+
+    match response.success {
+        true => Ok(response.data.into_iter().next().map(|a| a.url)),
+        false => Err(anyhow!("SteamGridDB did not return any assets")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn parses_assets() -> Result<()> {
+        let body = r#"{ "success": true, "data": [{ "id": 1, "url": "https://cdn2.steamgriddb.com/icon/a.png" }, { "id": 2, "url": "b.png" }] }"#;
+
+        assert_eq!(parse_assets(body)?.as_deref(), Some("https://cdn2.steamgriddb.com/icon/a.png"));
+        assert_eq!(parse_assets(r#"{ "success": true, "data": [] }"#)?, None);
+        assert!(parse_assets(r#"{ "success": false, "errors": ["Game not found"] }"#).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn builds_assets_url() {
+        assert_eq!(get_assets_url(AssetKind::Grids, 570), "https://www.steamgriddb.com/api/v2/grids/steam/570");
+    }
+}
@@ -0,0 +1,215 @@
+//! Client types for the Steam Store `appdetails` API.
+
+use crate::config;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+const APPDETAILS_BASE_URL: &str = "https://store.steampowered.com/api/appdetails";
+const ENGLISH: &str = "english";
+
+/// The language (`l=`) and country (`cc=`) to ask the store in. Without them, the store guesses both from the IP address.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct StoreLocale {
+    /// The language as Steam calls it, like `german` or `schinese`
+    pub language: Option<String>,
+    /// A two-letter country code, like `DE`
+    pub country: Option<String>,
+}
+
+impl StoreLocale {
+    pub fn from_config(config: &config::Store) -> Result<Self> {
+        let check = |setting: &str, value: &Option<String>| match value.as_deref().map(str::trim) {
+            None | Some("") => Ok(None),
+            Some(v) if v.chars().all(|c| c.is_ascii_alphabetic()) => Ok(Some(v.to_owned())),
+            Some(v) => Err(anyhow!("Invalid store.{setting} {v:?}, expected only letters")),
+        };
+
+        let country = check("country", &config.country)?;
+        if let Some(country) = country.as_ref().filter(|c| c.len() != 2) {
+            return Err(anyhow!("Invalid store.country {country:?}, expected a two-letter country code"));
+        }
+
+        Ok(Self {
+            language: check("language", &config.language)?.map(|l| l.to_lowercase()),
+            country: country.map(|c| c.to_uppercase()),
+        })
+    }
+
+    /// The same store, in English
+    pub fn english(&self) -> Self {
+        Self {
+            language: Some(ENGLISH.to_owned()),
+            country: self.country.clone(),
+        }
+    }
+
+    /// Checks if names might be translated, so a missing name might still be found in English
+    pub fn is_translated(&self) -> bool {
+        self.language.as_deref().is_some_and(|l| l != ENGLISH)
+    }
+
+    /// The query parameters for the store, also used to keep the documents of different locales apart.
+    ///
+    /// Empty for the store's own guess.
+    pub fn query(&self) -> String {
+        let language = self.language.as_ref().map(|l| format!("l={l}"));
+        let country = self.country.as_ref().map(|c| format!("cc={c}"));
+
+        [language, country].into_iter().flatten().collect::<Vec<_>>().join("&")
+    }
+
+    /// Adds the query parameters to a store url
+    pub fn localize(&self, url: &str) -> String {
+        match self.query() {
+            query if query.is_empty() => url.to_owned(),
+            query if url.contains('?') => format!("{url}&{query}"),
+            query => format!("{url}?{query}"),
+        }
+    }
+}
+
+/// The metadata of an app, as returned by the `appdetails` API
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AppDetails {
+    #[serde(rename = "type")]
+    pub app_type: String,
+    pub name: String,
+    pub steam_appid: u32,
+    pub header_image: Option<String>,
+    pub capsule_image: Option<String>,
+    #[serde(rename = "capsule_imagev5")]
+    pub capsule_image_v5: Option<String>,
+    #[serde(default)]
+    pub genres: Vec<Genre>,
+    #[serde(default)]
+    pub categories: Vec<Category>,
+    pub release_date: Option<ReleaseDate>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Genre {
+    pub id: String,
+    pub description: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Category {
+    pub id: u32,
+    pub description: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ReleaseDate {
+    pub coming_soon: bool,
+    pub date: String,
+}
+
+/// The response for a single app. `data` is missing when `success` is false.
+#[derive(Deserialize, Debug)]
+struct AppDetailsResponse {
+    success: bool,
+    data: Option<AppDetails>,
+}
+
+/// Gets the url of the `appdetails` API for an app, in the given language and country
+pub fn get_appdetails_url(app_id: u32, locale: &StoreLocale) -> String {
+    locale.localize(&format!("{APPDETAILS_BASE_URL}?appids={app_id}"))
+}
+
+/// Parses the response of the `appdetails` API.
+///
+/// Returns `Ok(None)` if the store does not know the app.
+pub fn parse_app_details(app_id: u32, body: &str) -> Result<Option<AppDetails>> {
+    let mut response: HashMap<String, AppDetailsResponse> = serde_json::from_str(body)?;
+
+    let app_response = response
+        .remove(&app_id.to_string())
+        .ok_or_else(|| anyhow!("The appdetails response does not contain app {app_id}"))?;
+
+    match (app_response.success, app_response.data) {
+        (true, Some(details)) => Ok(Some(details)),
+        _ => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    const DOTA_RESPONSE: &str = r#"{
+        "570": {
+            "success": true,
+            "data": {
+                "type": "game",
+                "name": "Dota 2",
+                "steam_appid": 570,
+                "required_age": 0,
+                "is_free": true,
+                "header_image": "https://shared.akamai.steamstatic.com/store_item_assets/steam/apps/570/header.jpg",
+                "capsule_image": "https://shared.akamai.steamstatic.com/store_item_assets/steam/apps/570/capsule_231x87.jpg",
+                "capsule_imagev5": "https://shared.akamai.steamstatic.com/store_item_assets/steam/apps/570/capsule_184x69.jpg",
+                "genres": [{ "id": "1", "description": "Action" }, { "id": "2", "description": "Strategy" }],
+                "categories": [{ "id": 1, "description": "Multi-player" }],
+                "release_date": { "coming_soon": false, "date": "9 Jul, 2013" }
+            }
+        }
+    }"#;
+
+    #[test]
+    fn renders_appdetails_url() {
+        let locale = StoreLocale { language: Some("german".to_owned()), country: Some("DE".to_owned()) };
+
+        assert_eq!(get_appdetails_url(570, &StoreLocale::default()), "https://store.steampowered.com/api/appdetails?appids=570");
+        assert_eq!(get_appdetails_url(570, &locale), "https://store.steampowered.com/api/appdetails?appids=570&l=german&cc=DE");
+    }
+
+    #[test]
+    fn localizes_store_urls() {
+        let locale = StoreLocale { language: None, country: Some("US".to_owned()) };
+
+        assert_eq!(locale.localize("https://store.steampowered.com/app/570/"), "https://store.steampowered.com/app/570/?cc=US");
+        assert_eq!(locale.english().query(), "l=english&cc=US");
+        assert!(!locale.is_translated());
+        assert!(StoreLocale { language: Some("schinese".to_owned()), country: None }.is_translated());
+    }
+
+    #[test]
+    fn reads_locale_from_config() -> Result<()> {
+        let config = config::Store { language: Some("German".to_owned()), country: Some("de".to_owned()) };
+
+        assert_eq!(StoreLocale::from_config(&config)?.query(), "l=german&cc=DE");
+        assert_eq!(StoreLocale::from_config(&config::Store { language: Some(String::new()), country: None })?, StoreLocale::default());
+        assert!(StoreLocale::from_config(&config::Store { language: Some("german&cc=US".to_owned()), country: None }).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn parses_app_details() -> Result<()> {
+        let details = parse_app_details(570, DOTA_RESPONSE)?.unwrap();
+
+        assert_eq!(details.name, "Dota 2");
+        assert_eq!(details.app_type, "game");
+        assert_eq!(details.genres.len(), 2);
+        assert_eq!(details.categories[0].description, "Multi-player");
+        assert_eq!(details.release_date.map(|r| r.date).as_deref(), Some("9 Jul, 2013"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn parses_unknown_app() -> Result<()> {
+        let details = parse_app_details(1, r#"{ "1": { "success": false } }"#)?;
+
+        assert!(details.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_response_for_other_app() {
+        assert!(parse_app_details(1, DOTA_RESPONSE).is_err());
+    }
+}
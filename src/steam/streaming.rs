@@ -0,0 +1,331 @@
+//! Detection of Steam Remote Play sessions hosted on this machine.
+//!
+//! The Steam client hosts streams in-process, so the only trace of a session is the client's
+//! `logs/streaming_log.txt`. We follow the log as it grows and check whether the last session that was started
+//! has been stopped since. Sessions from before the client was started never had their end logged, e.g. because
+//! Steam crashed, and are ignored.
+//!
+//! The markers below are not taken from a verified log sample, which is why Remote Play detection is off by default.
+
+use super::library::steam_roots;
+use once_cell::sync::Lazy;
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::UNIX_EPOCH,
+};
+use tracing::debug;
+
+const STREAMING_LOG_PATH: &str = "logs/streaming_log.txt";
+
+/// Log lines that mark the start of a streaming session
+const SESSION_START_MARKERS: [&str; 3] = [
+    "Starting streaming session",
+    "Streaming session started",
+    "CStreamingSession::Start",
+];
+/// Log lines that mark the end of a streaming session
+const SESSION_STOP_MARKERS: [&str; 4] = [
+    "Stopping streaming session",
+    "Streaming session stopped",
+    "Streaming session ended",
+    "CStreamingSession::Stop",
+];
+/// Log lines that show the session is a Remote Play Together session
+const TOGETHER_MARKERS: [&str; 2] = ["Remote Play Together", "RemotePlayTogether"];
+const GUEST_JOINED_MARKERS: [&str; 2] = ["Guest joined", "Adding guest"];
+const GUEST_LEFT_MARKERS: [&str; 2] = ["Guest left", "Removing guest"];
+/// Log lines start with the local time, like `[2026-10-18 20:00:00]`
+const TIMESTAMP_LENGTH: usize = "[2026-10-18 20:00:00]".len();
+/// UTC offsets are multiples of 15 minutes
+const UTC_OFFSET_STEP: i64 = 15 * 60;
+
+/// How far the log was read, so only new lines are read on the next call
+static LOG: Lazy<Mutex<Option<LogReader>>> = Lazy::new(|| Mutex::new(None));
+
+/// An active Remote Play session
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamingSession {
+    /// The session is a Remote Play Together session
+    pub together: bool,
+    /// The number of guests in a Remote Play Together session
+    pub guests: u32,
+}
+
+/// Finds an active Remote Play session in the logs of the Steam client that was started at `client_started_at`.
+///
+/// The log is only read when its modification time or length changed, and then only the lines added since.
+/// This does blocking file system access, so call it with `spawn_blocking` from async code.
+pub fn streaming_session(client_started_at: i64) -> Option<StreamingSession> {
+    let mut log = LOG.lock().unwrap();
+
+    let path = log
+        .as_ref()
+        .map(|r| r.path.clone())
+        .filter(|p| p.is_file())
+        .or_else(|| steam_roots().iter().map(|root| root.join(STREAMING_LOG_PATH)).find(|p| p.is_file()))?;
+    let metadata = path.metadata().ok()?;
+    let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?.as_secs() as i64;
+
+    // nothing was logged since the client was started
+    if modified < client_started_at {
+        return None;
+    }
+
+    // a log that got shorter was rotated or truncated, and is read from the start
+    let reader = match log.take() {
+        Some(reader) if reader.path == path && reader.client_started_at == client_started_at && reader.length <= metadata.len() => reader,
+        _ => LogReader::new(path, client_started_at),
+    };
+
+    log.insert(reader).update(modified, metadata.len())
+}
+
+/// Follows the streaming log of one run of the Steam client
+#[derive(Debug)]
+struct LogReader {
+    path: PathBuf,
+    client_started_at: i64,
+    /// The modification time of the log when it was last read
+    modified: i64,
+    /// Bytes read so far, up to the end of the last complete line
+    length: u64,
+    /// How far the local time of the log is ahead of UTC, once a line with a timestamp was read
+    utc_offset: Option<i64>,
+    session: SessionParser,
+}
+
+impl LogReader {
+    fn new(path: PathBuf, client_started_at: i64) -> Self {
+        Self { path, client_started_at, modified: 0, length: 0, utc_offset: None, session: SessionParser::default() }
+    }
+
+    /// Reads the lines added to the log since the last call, if it changed, and returns the current session
+    fn update(&mut self, modified: i64, length: u64) -> Option<StreamingSession> {
+        if modified == self.modified && length == self.length {
+            return self.session.session;
+        }
+
+        let lines = match read_lines(&self.path, &mut self.length) {
+            Ok(lines) => lines,
+            Err(e) => {
+                debug!("Error reading streaming log {:?}: {e}", self.path);
+                return self.session.session;
+            }
+        };
+        self.modified = modified;
+
+        // the last new line was logged when the log was last modified
+        if let Some(last_logged) = lines.iter().rev().find_map(|l| log_timestamp(l)) {
+            self.utc_offset = Some(utc_offset(last_logged, modified));
+        }
+        self.session.not_before = self.utc_offset.map(|offset| self.client_started_at + offset);
+
+        for line in &lines {
+            self.session.feed(line);
+        }
+
+        self.session.session
+    }
+}
+
+/// Reads the complete lines after `offset`, and moves `offset` to the end of the last one.
+///
+/// A line that is still being written is left for the next call.
+fn read_lines(path: &Path, offset: &mut u64) -> std::io::Result<Vec<String>> {
+    let mut file = BufReader::new(File::open(path)?);
+    file.seek(SeekFrom::Start(*offset))?;
+
+    let mut lines = Vec::new();
+    let mut line = Vec::new();
+
+    loop {
+        line.clear();
+        let read = file.read_until(b'\n', &mut line)?;
+
+        if read == 0 || line.last() != Some(&b'\n') {
+            return Ok(lines);
+        }
+
+        *offset += read as u64;
+        lines.push(String::from_utf8_lossy(&line).trim_end().to_owned());
+    }
+}
+
+/// Tracks the session that is running after the lines fed so far
+#[derive(Debug, Default)]
+struct SessionParser {
+    /// Sessions started before this time, in the local time of the log, are ignored
+    not_before: Option<i64>,
+    session: Option<StreamingSession>,
+}
+
+impl SessionParser {
+    fn feed(&mut self, line: &str) {
+        if contains_any(line, &SESSION_STOP_MARKERS) {
+            self.session = None;
+        } else if contains_any(line, &SESSION_START_MARKERS) {
+            let stale = self
+                .not_before
+                .zip(log_timestamp(line))
+                .is_some_and(|(not_before, started_at)| started_at < not_before);
+
+            self.session = match stale {
+                true => None,
+                false => Some(StreamingSession {
+                    together: contains_any(line, &TOGETHER_MARKERS),
+                    guests: 0,
+                }),
+            };
+        } else if let Some(s) = self.session.as_mut() {
+            if contains_any(line, &TOGETHER_MARKERS) {
+                s.together = true;
+            }
+            if contains_any(line, &GUEST_JOINED_MARKERS) {
+                s.together = true;
+                s.guests += 1;
+            } else if contains_any(line, &GUEST_LEFT_MARKERS) {
+                s.guests = s.guests.saturating_sub(1);
+            }
+        }
+    }
+}
+
+fn contains_any(line: &str, markers: &[&str]) -> bool {
+    markers.iter().any(|m| line.contains(m))
+}
+
+/// How far the local time of the log is ahead of UTC, in seconds, given the local time a line was logged at
+/// and the unix timestamp it was written at
+fn utc_offset(last_logged: i64, modified: i64) -> i64 {
+    ((last_logged - modified) as f64 / UTC_OFFSET_STEP as f64).round() as i64 * UTC_OFFSET_STEP
+}
+
+/// Reads the time at the start of a log line, in seconds since the epoch in the local time of the log
+fn log_timestamp(line: &str) -> Option<i64> {
+    let timestamp = line.get(..TIMESTAMP_LENGTH)?.strip_prefix('[')?.strip_suffix(']')?;
+    let (date, time) = timestamp.split_once(' ')?;
+
+    let date: Vec<i64> = date.split('-').map(str::parse).collect::<Result<_, _>>().ok()?;
+    let time: Vec<i64> = time.split(':').map(str::parse).collect::<Result<_, _>>().ok()?;
+
+    match (date.as_slice(), time.as_slice()) {
+        ([year, month, day], [hour, minute, second]) => {
+            Some(super::days_from_civil(*year, *month, *day) * 86400 + hour * 3600 + minute * 60 + second)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::io::Write;
+
+    /// 2026-10-18 20:00:00 as unix timestamp
+    const EVENING: i64 = 1_792_353_600;
+
+    fn parse_streaming_log(log: &str, not_before: Option<i64>) -> Option<StreamingSession> {
+        let mut parser = SessionParser { not_before, session: None };
+        log.lines().for_each(|line| parser.feed(line));
+
+        parser.session
+    }
+
+    #[test]
+    fn finds_running_session() {
+        let log = "\
+[2026-10-18 20:00:00] Starting streaming session to Steam Deck
+[2026-10-18 20:00:01] Streaming at 1280x800
+";
+
+        assert_eq!(parse_streaming_log(log, None), Some(StreamingSession { together: false, guests: 0 }));
+    }
+
+    #[test]
+    fn ignores_stopped_session() {
+        let log = "\
+[2026-10-18 20:00:00] Starting streaming session to Steam Deck
+[2026-10-18 21:00:00] Stopping streaming session
+";
+
+        assert_eq!(parse_streaming_log(log, None), None);
+    }
+
+    #[test]
+    fn counts_remote_play_together_guests() {
+        let log = "\
+[2026-10-18 20:00:00] Starting streaming session (Remote Play Together)
+[2026-10-18 20:00:05] Guest joined: friend1
+[2026-10-18 20:00:06] Guest joined: friend2
+[2026-10-18 20:10:00] Guest left: friend1
+";
+
+        assert_eq!(parse_streaming_log(log, None), Some(StreamingSession { together: true, guests: 1 }));
+    }
+
+    #[test]
+    fn ignores_sessions_from_before_client_start() {
+        // Steam crashed during the first session and was started again at 21:00
+        let log = "\
+[2026-10-18 20:00:00] Starting streaming session to Steam Deck
+[2026-10-18 21:00:30] Streaming log opened
+";
+        let not_before = log_timestamp("[2026-10-18 21:00:00]");
+
+        assert_eq!(parse_streaming_log(log, not_before), None);
+        assert!(parse_streaming_log(&format!("{log}[2026-10-18 21:05:00] Starting streaming session\n"), not_before).is_some());
+    }
+
+    #[test]
+    fn keeps_long_sessions_across_reads() -> std::io::Result<()> {
+        let path = std::env::temp_dir().join(format!("{}-streaming-log-{}.txt", crate::constants::APP_NAME, std::process::id()));
+        let mut file = File::create(&path)?;
+        writeln!(file, "[2026-10-18 20:00:00] Starting streaming session to Steam Deck")?;
+
+        let mut reader = LogReader::new(path.clone(), EVENING - 60);
+        let first = reader.update(EVENING, file.metadata()?.len());
+
+        // the start of the session is long gone from the end of the log, and the last line is not complete yet
+        for _ in 0..10_000 {
+            writeln!(file, "[2026-10-18 20:30:00] Streaming at 1280x800, 60 fps, 30 Mbit/s")?;
+        }
+        write!(file, "[2026-10-18 20:30:00] Stopping str")?;
+        let second = reader.update(EVENING + 1800, file.metadata()?.len());
+
+        writeln!(file, "eaming session")?;
+        let third = reader.update(EVENING + 1801, file.metadata()?.len());
+
+        std::fs::remove_file(&path)?;
+        assert_eq!(first, Some(StreamingSession { together: false, guests: 0 }));
+        assert_eq!(second, first);
+        assert_eq!(third, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn reads_log_timestamps() {
+        assert_eq!(log_timestamp("[2026-10-18 20:00:00] Starting streaming session"), Some(EVENING));
+        assert_eq!(log_timestamp("[1970-01-01 00:00:10]"), Some(10));
+        assert_eq!(log_timestamp("Starting streaming session"), None);
+        assert_eq!(log_timestamp("[2026-10-18T20:00:00]"), None);
+    }
+
+    #[test]
+    fn derives_utc_offset_from_last_line() {
+        let last_logged = log_timestamp("[2026-10-18 22:00:00]").unwrap();
+
+        // written at 20:00:02 UTC in UTC+2
+        assert_eq!(utc_offset(last_logged, EVENING + 2), 2 * 60 * 60);
+        assert_eq!(utc_offset(last_logged, EVENING + 2 * 60 * 60), 0);
+    }
+
+    #[test]
+    fn handles_empty_log() {
+        assert_eq!(parse_streaming_log("", None), None);
+    }
+}
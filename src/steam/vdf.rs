@@ -0,0 +1,251 @@
+//! Parser for Valve's text KeyValues format, used by `appmanifest_*.acf` and `libraryfolders.vdf`.
+
+use anyhow::{anyhow, bail, Result};
+use std::{iter::Peekable, str::Chars};
+
+/// A value in a KeyValues document
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyValue {
+    Value(String),
+    Section(Vec<(String, KeyValue)>),
+}
+
+impl KeyValue {
+    /// Gets the child with the given key. Keys are compared case-insensitively, like Steam does.
+    pub fn get(&self, key: &str) -> Option<&KeyValue> {
+        match self {
+            KeyValue::Section(children) => children
+                .iter()
+                .find(|(k, _)| k.eq_ignore_ascii_case(key))
+                .map(|(_, v)| v),
+            KeyValue::Value(_) => None,
+        }
+    }
+
+    /// Gets the child at the given path of keys
+    pub fn get_path(&self, path: &[&str]) -> Option<&KeyValue> {
+        path.iter().try_fold(self, |kv, key| kv.get(key))
+    }
+
+    /// Gets the string value at the given path of keys
+    pub fn get_str(&self, path: &[&str]) -> Option<&str> {
+        match self.get_path(path)? {
+            KeyValue::Value(v) => Some(v.as_str()),
+            KeyValue::Section(_) => None,
+        }
+    }
+
+    /// Gets the value at the given path of keys, parsed as a number
+    pub fn get_u64(&self, path: &[&str]) -> Option<u64> {
+        self.get_str(path)?.parse().ok()
+    }
+
+    /// The children of a section. Empty for a value.
+    pub fn children(&self) -> &[(String, KeyValue)] {
+        match self {
+            KeyValue::Section(children) => children,
+            KeyValue::Value(_) => &[],
+        }
+    }
+}
+
+/// Parses a KeyValues document.
+///
+/// The document is returned as a section containing the top level keys.
+pub fn parse(document: &str) -> Result<KeyValue> {
+    let mut tokens = Tokenizer { chars: document.chars().peekable() };
+
+    let children = parse_section(&mut tokens, true)?;
+
+    Ok(KeyValue::Section(children))
+}
+
+fn parse_section(tokens: &mut Tokenizer, top_level: bool) -> Result<Vec<(String, KeyValue)>> {
+    let mut children = Vec::new();
+
+    loop {
+        let key = match tokens.next_token()? {
+            None if top_level => return Ok(children),
+            None => bail!("Unexpected end of document, missing '}}'"),
+            Some(Token::Close) if !top_level => return Ok(children),
+            Some(Token::Close) => bail!("Unexpected '}}'"),
+            Some(Token::Open) => bail!("Expected a key, found '{{'"),
+            Some(Token::Condition) => bail!("Expected a key, found a condition"),
+            Some(Token::Text(key)) => key,
+        };
+
+        let value = match tokens.next_token()? {
+            Some(Token::Text(value)) => KeyValue::Value(value),
+            Some(Token::Open) => KeyValue::Section(parse_section(tokens, false)?),
+            _ => return Err(anyhow!("Expected a value for key {key:?}")),
+        };
+
+        // platform conditions like [$WIN32] are ignored, the value applies everywhere
+        tokens.skip_condition()?;
+
+        children.push((key, value));
+    }
+}
+
+enum Token {
+    Text(String),
+    Open,
+    Close,
+    Condition,
+}
+
+struct Tokenizer<'a> {
+    chars: Peekable<Chars<'a>>,
+}
+
+impl Tokenizer<'_> {
+    fn next_token(&mut self) -> Result<Option<Token>> {
+        self.skip_whitespace_and_comments();
+
+        let Some(c) = self.chars.next() else {
+            return Ok(None);
+        };
+
+        match c {
+            '{' => Ok(Some(Token::Open)),
+            '}' => Ok(Some(Token::Close)),
+            '"' => self.quoted().map(|t| Some(Token::Text(t))),
+            '[' => {
+                self.chars.by_ref().take_while(|c| *c != ']').for_each(drop);
+                Ok(Some(Token::Condition))
+            }
+            c => {
+                let mut text = String::from(c);
+                while let Some(&c) = self.chars.peek() {
+                    if c.is_whitespace() || matches!(c, '{' | '}' | '"') {
+                        break;
+                    }
+                    text.push(c);
+                    self.chars.next();
+                }
+                Ok(Some(Token::Text(text)))
+            }
+        }
+    }
+
+    fn quoted(&mut self) -> Result<String> {
+        let mut text = String::new();
+
+        loop {
+            match self.chars.next() {
+                None => bail!("Unterminated string"),
+                Some('"') => return Ok(text),
+                Some('\\') => match self.chars.next() {
+                    Some('n') => text.push('\n'),
+                    Some('t') => text.push('\t'),
+                    Some(c) => text.push(c),
+                    None => bail!("Unterminated string"),
+                },
+                Some(c) => text.push(c),
+            }
+        }
+    }
+
+    fn skip_condition(&mut self) -> Result<()> {
+        self.skip_whitespace_and_comments();
+
+        if self.chars.peek() == Some(&'[') {
+            self.next_token()?;
+        }
+
+        Ok(())
+    }
+
+    fn skip_whitespace_and_comments(&mut self) {
+        loop {
+            match self.chars.peek() {
+                Some(c) if c.is_whitespace() => {
+                    self.chars.next();
+                }
+                Some('/') => {
+                    // comments start with "//" and run until the end of the line
+                    let mut lookahead = self.chars.clone();
+                    lookahead.next();
+                    if lookahead.next() != Some('/') {
+                        return;
+                    }
+                    for c in self.chars.by_ref() {
+                        if c == '\n' {
+                            break;
+                        }
+                    }
+                }
+                _ => return,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    const MANIFEST: &str = r#"
+"AppState"
+{
+	"appid"		"1091500"
+	"name"		"Cyberpunk 2077"
+	"StateFlags"		"1026"
+	"BytesToDownload"		"2000"
+	"BytesDownloaded"		"500"
+	"UserConfig"
+	{
+		"language"		"english"
+	}
+	"MountedConfig"
+	{
+	}
+}
+"#;
+
+    #[test]
+    fn parses_manifest() -> Result<()> {
+        let kv = parse(MANIFEST)?;
+
+        assert_eq!(kv.get_str(&["AppState", "name"]), Some("Cyberpunk 2077"));
+        assert_eq!(kv.get_u64(&["AppState", "StateFlags"]), Some(1026));
+        assert_eq!(kv.get_str(&["AppState", "UserConfig", "language"]), Some("english"));
+        assert_eq!(kv.get_path(&["AppState", "MountedConfig"]), Some(&KeyValue::Section(vec![])));
+
+        Ok(())
+    }
+
+    #[test]
+    fn keys_are_case_insensitive() -> Result<()> {
+        let kv = parse(MANIFEST)?;
+
+        assert_eq!(kv.get_u64(&["appstate", "stateflags"]), Some(1026));
+
+        Ok(())
+    }
+
+    #[test]
+    fn handles_escapes_comments_and_conditions() -> Result<()> {
+        let kv = parse(r#"
+            // a comment
+            "root"
+            {
+                "quoted"    "a \"b\" c\\d"
+                unquoted    value
+                "platform"  "linux" [$LINUX]
+            }
+        "#)?;
+
+        assert_eq!(kv.get_str(&["root", "quoted"]), Some(r#"a "b" c\d"#));
+        assert_eq!(kv.get_str(&["root", "unquoted"]), Some("value"));
+        assert_eq!(kv.get_str(&["root", "platform"]), Some("linux"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_unclosed_sections() {
+        assert!(parse(r#""root" { "key" "value""#).is_err());
+    }
+}